@@ -0,0 +1,370 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use std::collections::HashMap;
+use common::{AudioType, AudioFrame};
+use audio::{Song, AudioData, AudioProcessor, seconds, from_seconds};
+use audio::spectrum::{Spectrum, FrequencyBands, BinMerger};
+use audio::structure::SectionFinder;
+use audio::midi::MidiFrame;
+
+// Change this whenever the analysis changes so old caches get redone
+const CACHE_VERSION : u32 = 7;
+const CACHE_MAGIC : &'static [u8] = b"VISANLYS";
+
+// Cached spectra are merged down to about this many bands, starting here
+const SPECTRUM_BANDS : usize = 128;
+const SPECTRUM_LO_HZ : f64 = 20.0;
+
+// Every component the analysis produces, in the order they are stored.
+// The ones saying where playback is, like Time and Progress, are added as
+// the song plays.
+pub const COMPONENTS : [AudioType; 20] = [
+    AudioType::Impulse,
+    AudioType::Level,
+    AudioType::LevelLeft,
+    AudioType::LevelRight,
+    AudioType::Balance,
+    AudioType::StereoWidth,
+    AudioType::Bass,
+    AudioType::Mid,
+    AudioType::Treble,
+    AudioType::Beat,
+    AudioType::BeatPhase,
+    AudioType::Bpm,
+    AudioType::Brightness,
+    AudioType::Flux,
+    AudioType::Rolloff,
+    AudioType::Noisiness,
+    AudioType::Pitch,
+    AudioType::Note,
+    AudioType::Section,
+    AudioType::SectionProgress,
+];
+
+// The audio components of a whole song worked out ahead of time, one frame
+// for every update, so playback only has to look them up
+pub struct Analysis {
+    frame_rate : f64,
+    frames : usize,
+    // Length of the audio, the last frame usually runs on past the end of it
+    seconds : f64,
+    // COMPONENTS.len() values for each frame
+    components : Vec<f32>,
+    // centres.len() bins for each frame
+    spectra : Vec<f32>,
+    // 12 pitch classes for each frame
+    chroma : Vec<f32>,
+    centres : Arc<Vec<f64>>,
+    // Integrated loudness of the whole song in LUFS
+    loudness : Option<f64>,
+}
+
+impl Analysis {
+    // Run a song to the end analysing it
+    pub fn analyse(song : &mut Song<Item=AudioData>, sample_time : f64) -> Self {
+        let sample_rate = song.sample_rate() as f64;
+        let mut audio_proc = AudioProcessor::new(sample_time, sample_rate, FrequencyBands::default());
+        let merger = BinMerger::new(audio_proc.spectrum_centres(), SPECTRUM_BANDS, SPECTRUM_LO_HZ);
+
+        let mut analysis = Analysis {
+            frame_rate : audio_proc.update_rate(),
+            frames : 0,
+            seconds : 0.0,
+            components : Vec::new(),
+            spectra : Vec::new(),
+            chroma : Vec::new(),
+            centres : merger.centres(),
+            loudness : None,
+        };
+
+        let mut samples = 0;
+        while let Some(data) = song.next() {
+            samples += 1;
+            if let Some(update) = audio_proc.process_sample(&data.samples) {
+                analysis.push(&update.audio, &merger);
+            }
+        }
+        analysis.seconds = samples as f64 / sample_rate;
+        audio_proc.flush(|update| analysis.push(&update.audio, &merger));
+        analysis.loudness = audio_proc.loudness();
+        analysis.mark_sections();
+
+        analysis
+    }
+
+    // Section and SectionProgress need the whole song, so are filled in at the end
+    fn mark_sections(&mut self) {
+        let bins = self.centres.len();
+        let mut finder = SectionFinder::new(self.frame_rate);
+        for i in 0 .. self.frames {
+            finder.push(&self.spectra[i * bins .. (i + 1) * bins], &self.chroma[i * 12 .. (i + 1) * 12]);
+        }
+
+        let mut starts = finder.sections();
+        starts.push(self.frames);
+
+        let n = COMPONENTS.len();
+        let section = COMPONENTS.iter().position(|c| *c == AudioType::Section).unwrap();
+        let progress = COMPONENTS.iter().position(|c| *c == AudioType::SectionProgress).unwrap();
+        for (s, w) in starts.windows(2).enumerate() {
+            let (start, end) = (w[0], w[1]);
+            for i in start .. end {
+                self.components[i * n + section] = s as f32;
+                self.components[i * n + progress] = (i - start) as f32 / (end - start) as f32;
+            }
+        }
+    }
+
+    fn push(&mut self, frame : &AudioFrame, merger : &BinMerger) {
+        for t in COMPONENTS.iter() {
+            let x = frame.components.get(t).cloned().unwrap_or(0.0);
+            self.components.push(x as f32);
+        }
+        let spectrum = merger.merge(&frame.spectrum);
+        self.spectra.extend(spectrum.bins.iter().map(|&a| a as f32));
+        self.chroma.extend(frame.chroma.iter().map(|&x| x as f32));
+        self.frames += 1;
+    }
+
+    // Frames per second
+    pub fn frame_rate(&self) -> f64 {
+        self.frame_rate
+    }
+
+    pub fn loudness(&self) -> Option<f64> {
+        self.loudness
+    }
+
+    // The largest value a component reaches over the whole song
+    pub fn peak(&self, t : AudioType) -> f64 {
+        let n = COMPONENTS.len();
+        match COMPONENTS.iter().position(|c| *c == t) {
+            Some(c) => self.components.chunks(n).fold(0.0, |acc, frame| acc.max(frame[c] as f64)),
+            None => 0.0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames
+    }
+
+    // How long the song plays for, which the clock stops at
+    pub fn duration(&self) -> Duration {
+        from_seconds(self.seconds)
+    }
+
+    // The frame covering the given point in the song
+    pub fn index_at(&self, time : Duration) -> usize {
+        // Nudged up so the start of a frame doesn't round into the one before
+        (seconds(time) * self.frame_rate + 1e-6) as usize
+    }
+
+    pub fn time_of(&self, i : usize) -> Duration {
+        from_seconds(i as f64 / self.frame_rate)
+    }
+
+    pub fn frame(&self, i : usize) -> AudioFrame {
+        let n = COMPONENTS.len();
+        let components = COMPONENTS.iter()
+                                   .cloned()
+                                   .zip(self.components[i * n .. (i + 1) * n].iter().map(|&x| x as f64))
+                                   .collect::<HashMap<AudioType, f64>>();
+
+        let bins = self.centres.len();
+        let spectrum = Spectrum {
+            bins : self.spectra[i * bins .. (i + 1) * bins].iter().map(|&a| a as f64).collect(),
+            centres : self.centres.clone(),
+        };
+
+        let mut chroma = [0.0; 12];
+        for (c, &x) in chroma.iter_mut().zip(self.chroma[i * 12 .. (i + 1) * 12].iter()) {
+            *c = x as f64;
+        }
+
+        AudioFrame {
+            components : components,
+            spectrum : spectrum,
+            chroma : chroma,
+            midi : MidiFrame::default(),
+        }
+    }
+}
+
+// Use the analysis cached next to the song if it's still up to date,
+// otherwise analyse the song and cache it for next time
+pub fn load_or_analyse(song : &mut Song<Item=AudioData>, path : &Path, sample_time : f64) -> Analysis {
+    let cache = cache_path(path);
+    if let Ok(analysis) = read_cache(&cache, path, sample_time) {
+        return analysis;
+    }
+
+    println!("Analysing {}, this only happens the first time it's played", path.display());
+    let analysis = Analysis::analyse(song, sample_time);
+
+    if let Err(e) = write_cache(&analysis, &cache, path, sample_time) {
+        println!("Couldn't save the analysis to {}: {}", cache.display(), e);
+    }
+    analysis
+}
+
+fn cache_path(path : &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".analysis");
+    PathBuf::from(name)
+}
+
+// Size and modification time of the song, if either changes the cache is stale
+fn fingerprint(path : &Path) -> io::Result<(u64, u64)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified()?
+                           .duration_since(UNIX_EPOCH)
+                           .map(|d| d.as_secs())
+                           .unwrap_or(0);
+    Ok((metadata.len(), modified))
+}
+
+// The cache is a header describing the song and the layout followed by every
+// frame's components, spectrum and chroma, all little endian
+fn write_cache(analysis : &Analysis, cache : &Path, song_path : &Path, sample_time : f64) -> io::Result<()> {
+    let (len, modified) = fingerprint(song_path)?;
+    let mut w = BufWriter::new(File::create(cache)?);
+
+    w.write_all(CACHE_MAGIC)?;
+    write_u32(&mut w, CACHE_VERSION)?;
+    write_u64(&mut w, len)?;
+    write_u64(&mut w, modified)?;
+    write_f64(&mut w, sample_time)?;
+    write_f64(&mut w, analysis.frame_rate)?;
+    write_u64(&mut w, analysis.frames as u64)?;
+    write_f64(&mut w, analysis.seconds)?;
+    // NaN if there wasn't one
+    write_f64(&mut w, analysis.loudness.unwrap_or(::std::f64::NAN))?;
+
+    write_u32(&mut w, analysis.centres.len() as u32)?;
+    for &c in analysis.centres.iter() {
+        write_f64(&mut w, c)?;
+    }
+
+    let n = COMPONENTS.len();
+    let bins = analysis.centres.len();
+    for i in 0 .. analysis.frames {
+        for &x in &analysis.components[i * n .. (i + 1) * n] {
+            write_f32(&mut w, x)?;
+        }
+        for &a in &analysis.spectra[i * bins .. (i + 1) * bins] {
+            write_f32(&mut w, a)?;
+        }
+        for &x in &analysis.chroma[i * 12 .. (i + 1) * 12] {
+            write_f32(&mut w, x)?;
+        }
+    }
+    w.flush()
+}
+
+fn read_cache(cache : &Path, song_path : &Path, sample_time : f64) -> io::Result<Analysis> {
+    let file = File::open(cache)?;
+    let file_len = file.metadata()?.len();
+    let mut r = BufReader::new(file);
+
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    let up_to_date = &magic[..] == CACHE_MAGIC
+        && read_u32(&mut r)? == CACHE_VERSION
+        && (read_u64(&mut r)?, read_u64(&mut r)?) == fingerprint(song_path)?
+        && read_f64(&mut r)? == sample_time;
+    if !up_to_date {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "analysis is out of date"));
+    }
+
+    let frame_rate = read_f64(&mut r)?;
+    let frames = read_u64(&mut r)? as usize;
+    let seconds = read_f64(&mut r)?;
+    let loudness = read_f64(&mut r)?;
+    let loudness = if loudness.is_nan() { None } else { Some(loudness) };
+
+    let bins = read_u32(&mut r)? as usize;
+
+    // A damaged header could ask for far more than the file holds, so check
+    // before setting aside room for it all
+    let n = COMPONENTS.len();
+    let needed = bins.checked_add(n + 12)
+                     .and_then(|x| x.checked_mul(4))
+                     .and_then(|x| x.checked_mul(frames))
+                     .and_then(|x| bins.checked_mul(8).and_then(|y| x.checked_add(y)));
+    match needed {
+        Some(x) if x as u64 <= file_len && seconds >= 0.0 => {}
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "analysis is damaged")),
+    }
+
+    let mut centres = Vec::with_capacity(bins);
+    for _ in 0 .. bins {
+        centres.push(read_f64(&mut r)?);
+    }
+
+    let mut components = Vec::with_capacity(frames * n);
+    let mut spectra = Vec::with_capacity(frames * bins);
+    let mut chroma = Vec::with_capacity(frames * 12);
+    for _ in 0 .. frames {
+        for _ in 0 .. n {
+            components.push(read_f32(&mut r)?);
+        }
+        for _ in 0 .. bins {
+            spectra.push(read_f32(&mut r)?);
+        }
+        for _ in 0 .. 12 {
+            chroma.push(read_f32(&mut r)?);
+        }
+    }
+
+    Ok(Analysis {
+        frame_rate : frame_rate,
+        frames : frames,
+        seconds : seconds,
+        components : components,
+        spectra : spectra,
+        chroma : chroma,
+        centres : Arc::new(centres),
+        loudness : loudness,
+    })
+}
+
+fn write_u32<W : Write>(w : &mut W, x : u32) -> io::Result<()> {
+    let bytes = [x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8];
+    w.write_all(&bytes)
+}
+
+fn write_u64<W : Write>(w : &mut W, x : u64) -> io::Result<()> {
+    write_u32(w, x as u32)?;
+    write_u32(w, (x >> 32) as u32)
+}
+
+fn write_f32<W : Write>(w : &mut W, x : f32) -> io::Result<()> {
+    write_u32(w, x.to_bits())
+}
+
+fn write_f64<W : Write>(w : &mut W, x : f64) -> io::Result<()> {
+    write_u64(w, x.to_bits())
+}
+
+fn read_u32<R : Read>(r : &mut R) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok((b[0] as u32) | ((b[1] as u32) << 8) | ((b[2] as u32) << 16) | ((b[3] as u32) << 24))
+}
+
+fn read_u64<R : Read>(r : &mut R) -> io::Result<u64> {
+    let lo = read_u32(r)? as u64;
+    let hi = read_u32(r)? as u64;
+    Ok(lo | (hi << 32))
+}
+
+fn read_f32<R : Read>(r : &mut R) -> io::Result<f32> {
+    read_u32(r).map(f32::from_bits)
+}
+
+fn read_f64<R : Read>(r : &mut R) -> io::Result<f64> {
+    read_u64(r).map(f64::from_bits)
+}
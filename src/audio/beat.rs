@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+use audio::spectrum::Spectrum;
+
+// How many seconds of onset history we keep for tempo estimation
+const HISTORY_SECONDS : f64 = 6.0;
+
+// Range of tempos we search over, and the tempo we prefer when the
+// autocorrelation is ambiguous
+const MIN_BPM : f64 = 60.0;
+const MAX_BPM : f64 = 200.0;
+const PREFERRED_BPM : f64 = 120.0;
+
+// Fraction of a beat period an onset may be off from the predicted beat
+// and still be counted as that beat
+const BEAT_TOLERANCE : f64 = 0.2;
+
+// How long the Beat pulse takes to decay to zero
+const PULSE_SECONDS : f64 = 0.1;
+
+pub struct BeatState {
+    // 1.0 on a beat decaying to 0.0
+    pub beat : f64,
+    // Ramps from 0.0 to 1.0 between beats
+    pub phase : f64,
+    // Zero until we have a tempo estimate
+    pub bpm : f64,
+}
+
+// Detects onsets using spectral flux, estimates the tempo by autocorrelating
+// the onset strength, and follows the beat with a flywheel that is pulled
+// back into line by onsets near where a beat is expected.
+// All times are measured in analysis frames.
+pub struct BeatTracker {
+    frame_rate : f64,
+    frame : usize,
+
+    prev_bins : Vec<f64>,
+    onsets : VecDeque<f64>,
+
+    // Frames between beats, if we have a tempo
+    period : Option<f64>,
+    last_beat : f64,
+    pulse : f64,
+}
+
+impl BeatTracker {
+    pub fn new(frame_rate : f64) -> Self {
+        BeatTracker {
+            frame_rate : frame_rate,
+            frame : 0,
+            prev_bins : Vec::new(),
+            onsets : VecDeque::new(),
+            period : None,
+            last_beat : 0.0,
+            pulse : 0.0,
+        }
+    }
+
+    pub fn update(&mut self, spectrum : &Spectrum) -> BeatState {
+        let flux = self.flux(spectrum);
+
+        self.onsets.push_back(flux);
+        let history_len = (HISTORY_SECONDS * self.frame_rate) as usize;
+        while self.onsets.len() > history_len {
+            self.onsets.pop_front();
+        }
+
+        // Re-estimate the tempo twice a second once we have a few seconds to go on
+        let estimate_every = ((self.frame_rate / 2.0) as usize).max(1);
+        if self.onsets.len() * 2 >= history_len && self.frame % estimate_every == 0 {
+            self.estimate_tempo();
+        }
+
+        self.pulse = (self.pulse - 1.0 / (PULSE_SECONDS * self.frame_rate)).max(0.0);
+
+        if self.previous_was_onset() {
+            let onset_frame = self.frame as f64 - 1.0;
+            self.onset(onset_frame);
+        }
+        self.flywheel();
+
+        self.frame += 1;
+
+        BeatState {
+            beat : self.pulse,
+            phase : self.phase(),
+            bpm : self.period.map(|p| 60.0 * self.frame_rate / p).unwrap_or(0.0),
+        }
+    }
+
+    // Sum of the increases in log magnitude across the spectrum
+    fn flux(&mut self, spectrum : &Spectrum) -> f64 {
+        let bins = spectrum.bins.iter()
+                                .map(|a| (1.0 + 100.0 * a).ln())
+                                .collect::<Vec<f64>>();
+
+        let flux = if self.prev_bins.len() == bins.len() && !bins.is_empty() {
+            let total = bins.iter()
+                            .zip(self.prev_bins.iter())
+                            .fold(0.0, |acc, (b, p)| acc + (b - p).max(0.0));
+            total / bins.len() as f64
+        }
+        else {
+            0.0
+        };
+
+        self.prev_bins = bins;
+        flux
+    }
+
+    // An onset is a local peak in the flux that stands out above the recent average
+    fn previous_was_onset(&self) -> bool {
+        let n = self.onsets.len();
+        if n < 3 {
+            return false;
+        }
+
+        let (before, peak, after) = (self.onsets[n - 3], self.onsets[n - 2], self.onsets[n - 1]);
+
+        // Average over about the last third of a second
+        let recent = ((self.frame_rate / 3.0) as usize).max(1).min(n);
+        let mean = self.onsets.iter().skip(n - recent).fold(0.0, |acc, x| acc + x) / recent as f64;
+
+        peak > before && peak >= after && peak > mean * 1.5 + 0.001
+    }
+
+    fn onset(&mut self, onset_frame : f64) {
+        match self.period {
+            Some(period) => {
+                let tolerance = BEAT_TOLERANCE * period;
+                let since_beat = onset_frame - self.last_beat;
+                let until_beat = self.last_beat + period - onset_frame;
+
+                if since_beat < tolerance {
+                    // The flywheel fired slightly early, nudge it back
+                    self.last_beat = onset_frame;
+                }
+                else if until_beat < tolerance {
+                    // The beat came slightly early
+                    self.beat(onset_frame);
+                }
+            }
+            None => {
+                // Without a tempo every onset is a beat
+                self.beat(onset_frame);
+            }
+        }
+    }
+
+    // Fire beats where the tempo predicts them
+    fn flywheel(&mut self) {
+        if let Some(period) = self.period {
+            let now = self.frame as f64;
+            while now >= self.last_beat + period {
+                let next = self.last_beat + period;
+                self.beat(next);
+            }
+        }
+    }
+
+    fn beat(&mut self, frame : f64) {
+        self.last_beat = frame;
+        self.pulse = 1.0;
+    }
+
+    fn phase(&self) -> f64 {
+        match self.period {
+            Some(period) => {
+                let phase = (self.frame as f64 - self.last_beat) / period;
+                phase.max(0.0).min(1.0)
+            }
+            None => 0.0,
+        }
+    }
+
+    // Autocorrelate the onset strength over the range of plausible beat periods,
+    // weighted towards the preferred tempo to avoid locking onto half or double time
+    fn estimate_tempo(&mut self) {
+        let n = self.onsets.len();
+        let mean = self.onsets.iter().fold(0.0, |acc, x| acc + x) / n as f64;
+        let centred = self.onsets.iter().map(|x| x - mean).collect::<Vec<f64>>();
+
+        let min_lag = (60.0 * self.frame_rate / MAX_BPM).floor().max(1.0) as usize;
+        let max_lag = ((60.0 * self.frame_rate / MIN_BPM).ceil() as usize).min(n - 1);
+        if min_lag + 2 > max_lag {
+            return;
+        }
+
+        let preferred_lag = 60.0 * self.frame_rate / PREFERRED_BPM;
+
+        let correlation = (min_lag .. max_lag + 1).map(|lag| {
+            let r = (lag .. n).fold(0.0, |acc, i| acc + centred[i] * centred[i - lag]);
+            let octaves = (lag as f64 / preferred_lag).log2();
+            r * (-0.5 * octaves * octaves).exp()
+        }).collect::<Vec<f64>>();
+
+        let mut best = 0;
+        for i in 1 .. correlation.len() {
+            if correlation[i] > correlation[best] {
+                best = i;
+            }
+        }
+        let best_r = correlation[best];
+
+        // No periodicity at all, probably silence
+        if best_r <= 0.0 {
+            return;
+        }
+
+        // Parabolic interpolation around the peak for sub-frame accuracy
+        let mut lag = (best + min_lag) as f64;
+        if best > 0 && best + 1 < correlation.len() {
+            let (a, b, c) = (correlation[best - 1], best_r, correlation[best + 1]);
+            let denom = a - 2.0 * b + c;
+            if denom != 0.0 {
+                lag += 0.5 * (a - c) / denom;
+            }
+        }
+
+        self.period = Some(match self.period {
+            // Smooth small changes, jump straight to a new tempo
+            Some(p) if (lag - p).abs() < 0.1 * p => 0.8 * p + 0.2 * lag,
+            _ => lag,
+        });
+    }
+}
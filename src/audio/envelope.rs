@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use common::AudioType;
+use audio::seconds;
+
+// A gap in the updates longer than this is a seek rather than the audio
+// carrying on, so the envelopes start again from where we land
+const MAX_GAP_SECONDS : f64 = 1.0;
+
+// How quickly a component follows the audio, chosen in the script.
+// Times are in seconds for the value to get most of the way to a new level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Envelope {
+    // While the value is rising
+    pub attack : f64,
+    // While the value is falling
+    pub release : f64,
+}
+
+impl Default for Envelope {
+    // Follows the audio straight away
+    fn default() -> Self {
+        Envelope {
+            attack : 0.0,
+            release : 0.0,
+        }
+    }
+}
+
+// Smooths the components the script asked for as updates go past
+pub struct EnvelopeFollower {
+    envelopes : HashMap<AudioType, Envelope>,
+    values : HashMap<AudioType, f64>,
+    last_time : Option<f64>,
+}
+
+impl EnvelopeFollower {
+    pub fn new(envelopes : HashMap<AudioType, Envelope>) -> Self {
+        EnvelopeFollower {
+            envelopes : envelopes,
+            values : HashMap::new(),
+            last_time : None,
+        }
+    }
+
+    // Smooth the components of the update at the given time
+    pub fn apply(&mut self, components : &mut HashMap<AudioType, f64>, time : Duration) {
+        let time = seconds(time);
+        let dt = match self.last_time {
+            Some(last) if time > last && time - last <= MAX_GAP_SECONDS => time - last,
+            Some(_) => {
+                self.values.clear();
+                0.0
+            }
+            None => 0.0,
+        };
+        self.last_time = Some(time);
+
+        for (t, envelope) in self.envelopes.iter() {
+            let target = match components.get(t) {
+                Some(&x) => x,
+                None => continue,
+            };
+
+            let value = match self.values.get(t) {
+                Some(&y) => {
+                    let time_constant = if target > y { envelope.attack } else { envelope.release };
+                    follow(y, target, time_constant, dt)
+                }
+                None => target,
+            };
+
+            self.values.insert(t.clone(), value);
+            components.insert(t.clone(), value);
+        }
+    }
+}
+
+// Move a value towards its target as a one pole filter would over dt seconds
+fn follow(value : f64, target : f64, time_constant : f64, dt : f64) -> f64 {
+    if time_constant <= 0.0 {
+        target
+    }
+    else {
+        target + (value - target) * (-dt / time_constant).exp()
+    }
+}
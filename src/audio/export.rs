@@ -0,0 +1,129 @@
+use std::fs::File;
+use std::io::{self, Write, BufWriter};
+use std::path::Path;
+use std::time::Duration;
+use std::collections::HashMap;
+use common::AudioType;
+use audio::{Song, AudioData, AudioProcessor, seconds};
+use audio::analysis::{Analysis, COMPONENTS};
+use audio::spectrum::FrequencyBands;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    // Worked out from the extension of the file being written
+    pub fn from_path(path : &Path) -> Option<Self> {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        match ext.as_ref() {
+            "csv" => Some(ExportFormat::Csv),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+// Run the analysis over a whole song and write out every component at every
+// update, with a column or field for each component named as it is in scripts.
+// Returns how many updates were written.
+pub fn export(song : &mut Song<Item=AudioData>, sample_time : f64, out : &Path, format : ExportFormat) -> io::Result<usize> {
+    let mut w = BufWriter::new(File::create(out)?);
+
+    let written = if song.is_live() {
+        export_live(song, &mut w, format)?
+    }
+    else {
+        // The same analysis the visuals see, with the parts that need the whole song
+        let analysis = Analysis::analyse(song, sample_time);
+        write_header(&mut w, format, analysis.frame_rate())?;
+        for i in 0 .. analysis.len() {
+            write_update(&mut w, analysis.time_of(i), &analysis.frame(i).components, format, i)?;
+        }
+        analysis.len()
+    };
+
+    if format == ExportFormat::Json {
+        writeln!(w, "\n  ]")?;
+        writeln!(w, "}}")?;
+    }
+    w.flush()?;
+
+    Ok(written)
+}
+
+// Live audio can't be analysed ahead, so is written as it arrives
+fn export_live<W : Write>(song : &mut Song<Item=AudioData>, w : &mut W, format : ExportFormat) -> io::Result<usize> {
+    let mut audio_proc = AudioProcessor::live(song.sample_rate() as f64, FrequencyBands::default());
+    write_header(w, format, audio_proc.update_rate())?;
+
+    let mut written = 0;
+    while let Some(data) = song.next() {
+        if let Some(update) = audio_proc.process_sample(&data.samples) {
+            write_update(w, update.time, &update.audio.components, format, written)?;
+            written += 1;
+        }
+    }
+
+    // The end of the audio hasn't reached the present of the windows yet
+    let mut tail = Vec::new();
+    audio_proc.flush(|update| tail.push(update));
+    for update in tail.iter() {
+        write_update(w, update.time, &update.audio.components, format, written)?;
+        written += 1;
+    }
+
+    Ok(written)
+}
+
+fn write_header<W : Write>(w : &mut W, format : ExportFormat, update_rate : f64) -> io::Result<()> {
+    match format {
+        ExportFormat::Csv => {
+            write!(w, "time")?;
+            for t in COMPONENTS.iter() {
+                write!(w, ",{:?}", t)?;
+            }
+            writeln!(w, "")
+        }
+        ExportFormat::Json => {
+            writeln!(w, "{{")?;
+            writeln!(w, "  \"update_rate\": {},", update_rate)?;
+            write!(w, "  \"updates\": [")
+        }
+    }
+}
+
+// index is how many updates have been written before this one
+fn write_update<W : Write>(w : &mut W, time : Duration, components : &HashMap<AudioType, f64>, format : ExportFormat, index : usize) -> io::Result<()> {
+    let values = COMPONENTS.iter().map(|t| {
+        components.get(t).cloned().unwrap_or(0.0)
+    });
+
+    match format {
+        ExportFormat::Csv => {
+            write!(w, "{:.6}", seconds(time))?;
+            for x in values {
+                write!(w, ",{}", x)?;
+            }
+            writeln!(w, "")
+        }
+        ExportFormat::Json => {
+            if index > 0 {
+                write!(w, ",")?;
+            }
+            write!(w, "\n    {{\"time\": {:.6}", seconds(time))?;
+            for (t, x) in COMPONENTS.iter().zip(values) {
+                // JSON has no way to write NaN or infinity
+                if x.is_finite() {
+                    write!(w, ", \"{:?}\": {}", t, x)?;
+                }
+                else {
+                    write!(w, ", \"{:?}\": null", t)?;
+                }
+            }
+            write!(w, "}}")
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Complex {
+    pub re : f64,
+    pub im : f64,
+}
+
+impl Complex {
+    pub fn new(re : f64, im : f64) -> Self {
+        Complex {
+            re : re,
+            im : im,
+        }
+    }
+
+    pub fn norm_sqr(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn norm(&self) -> f64 {
+        self.norm_sqr().sqrt()
+    }
+
+    fn mul(&self, other : &Complex) -> Complex {
+        Complex::new(self.re * other.re - self.im * other.im,
+                     self.re * other.im + self.im * other.re)
+    }
+}
+
+// A radix-2 FFT of a fixed size, with the twiddle factors worked out up front
+// so we aren't calling sin/cos for every update
+pub struct Fft {
+    size : usize,
+    twiddles : Vec<Complex>,
+}
+
+impl Fft {
+    pub fn new(size : usize) -> Self {
+        if !size.is_power_of_two() {
+            panic!("Tried to create an FFT of size {}, which isn't a power of two!", size);
+        }
+
+        let twiddles = (0 .. size / 2).map(|k| {
+            let angle = -2.0 * PI * (k as f64) / (size as f64);
+            Complex::new(angle.cos(), angle.sin())
+        }).collect();
+
+        Fft {
+            size : size,
+            twiddles : twiddles,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // In place iterative Cooley-Tukey
+    pub fn process(&self, buf : &mut [Complex]) {
+        let n = self.size;
+        assert_eq!(buf.len(), n);
+
+        // Reorder into bit reversed order
+        let mut j = 0;
+        for i in 1 .. n {
+            let mut bit = n >> 1;
+            while j & bit != 0 {
+                j ^= bit;
+                bit >>= 1;
+            }
+            j |= bit;
+            if i < j {
+                buf.swap(i, j);
+            }
+        }
+
+        // Combine butterflies, doubling the length each pass
+        let mut len = 2;
+        while len <= n {
+            let half = len / 2;
+            let stride = n / len;
+            let mut start = 0;
+            while start < n {
+                for k in 0 .. half {
+                    let w = self.twiddles[k * stride];
+                    let a = buf[start + k];
+                    let b = buf[start + k + half].mul(&w);
+                    buf[start + k] = Complex::new(a.re + b.re, a.im + b.im);
+                    buf[start + k + half] = Complex::new(a.re - b.re, a.im - b.im);
+                }
+                start += len;
+            }
+            len <<= 1;
+        }
+    }
+}
+
+// Hann window coefficients of length n
+pub fn hann_window(n : usize) -> Vec<f64> {
+    (0 .. n).map(|i| {
+        0.5 - 0.5 * (2.0 * PI * (i as f64) / (n as f64)).cos()
+    }).collect()
+}
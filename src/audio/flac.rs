@@ -0,0 +1,100 @@
+extern crate claxon;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use self::claxon::{FlacReader, Block, Error};
+use audio::{Song, AudioData, from_seconds};
+use audio::playback::DecodedAudio;
+
+pub struct FlacSong<T : Read> {
+    reader : FlacReader<T>,
+    current_block : Option<Block>,
+    current_block_sample : u32,
+    current_sample : usize,
+    channels : usize,
+    sample_rate : u32,
+    // Multiplying by this normalises a sample to -1..1
+    scale : f32,
+    name : PathBuf,
+}
+
+impl<T : Read> FlacSong<T> {
+    pub fn new(t : T, path : &Path) -> Result<Self, Error> {
+        let reader = FlacReader::new(t)?;
+        let info = reader.streaminfo();
+        Ok(FlacSong {
+            reader : reader,
+            current_block : None,
+            current_block_sample : 0,
+            current_sample : 0,
+            channels : info.channels as usize,
+            sample_rate : info.sample_rate,
+            scale : 1.0 / (1i64 << (info.bits_per_sample - 1)) as f32,
+            name : path.to_path_buf(),
+        })
+    }
+
+    // Decode the next block, reusing the old block's buffer
+    // Returns whether it was successful
+    fn next_block(&mut self) -> bool {
+        let buffer = self.current_block.take()
+                                       .map(|b| b.into_buffer())
+                                       .unwrap_or_else(Vec::new);
+        match self.reader.blocks().read_next_or_eof(buffer) {
+            Ok(Some(block)) => {
+                self.current_block = Some(block);
+                self.current_block_sample = 0;
+                true
+            }
+            Ok(None) => false,
+            Err(e) => {
+                println!("Error decoding flac: {:?}", e);
+                false
+            }
+        }
+    }
+
+    fn block_finished(&self) -> bool {
+        match self.current_block {
+            Some(ref block) => self.current_block_sample >= block.duration(),
+            None => true,
+        }
+    }
+}
+
+impl<T : Read> Song for FlacSong<T> {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn decode(&self) -> Option<DecodedAudio> {
+        DecodedAudio::from_file(&self.name, |file| FlacSong::new(file, &self.name))
+    }
+}
+
+impl<T : Read> Iterator for FlacSong<T> {
+    type Item = AudioData;
+    fn next(&mut self) -> Option<Self::Item> {
+        // Blocks can technically be empty so keep going until we find samples
+        while self.block_finished() {
+            if !self.next_block() {
+                return None;
+            }
+        }
+
+        let samples = {
+            let block = self.current_block.as_ref().unwrap();
+            (0 .. self.channels).map(|c| {
+                block.sample(c as u32, self.current_block_sample) as f32 * self.scale
+            }).collect::<Vec<f32>>()
+        };
+
+        let t = self.current_sample as f64 / self.sample_rate as f64;
+        let dur = from_seconds(t);
+        self.current_block_sample += 1;
+        self.current_sample += 1;
+        Some(AudioData {
+            time : dur,
+            samples : samples,
+        })
+    }
+}
@@ -0,0 +1,212 @@
+use std::f64::consts::PI;
+use std::collections::VecDeque;
+
+// Loudness is measured over blocks of this many seconds, overlapping by
+// three quarters, as in ITU-R BS.1770
+const BLOCK_SECONDS : f64 = 0.4;
+const BLOCK_STEPS : usize = 4;
+
+// Blocks quieter than this are silence and are ignored
+const ABSOLUTE_GATE : f64 = -70.0;
+// Blocks this far below the average are ignored too, so quiet passages
+// don't drag the loudness down
+const RELATIVE_GATE : f64 = -10.0;
+
+// A second order IIR filter
+#[derive(Clone)]
+struct Biquad {
+    b : [f64; 3],
+    a : [f64; 2],
+    // The last two inputs and outputs
+    x : [f64; 2],
+    y : [f64; 2],
+}
+
+impl Biquad {
+    fn new(b : [f64; 3], a : [f64; 2]) -> Self {
+        Biquad {
+            b : b,
+            a : a,
+            x : [0.0; 2],
+            y : [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x : f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+              - self.a[0] * self.y[0] - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+// The K weighting filter, a shelf boosting the highs the way the head does
+// followed by a high pass cutting out what we can barely hear.
+// Coefficients are worked out for any sample rate as in libebur128.
+#[derive(Clone)]
+struct KWeighting {
+    shelf : Biquad,
+    high_pass : Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate : f64) -> Self {
+        let f0 = 1681.974450955533;
+        let gain = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / sample_rate).tan();
+        let vh = 10.0f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0]);
+
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0]);
+
+        KWeighting {
+            shelf : shelf,
+            high_pass : high_pass,
+        }
+    }
+
+    fn process(&mut self, x : f64) -> f64 {
+        let x = self.shelf.process(x);
+        self.high_pass.process(x)
+    }
+}
+
+// Measures the integrated loudness of everything it has been given, in LUFS
+pub struct LoudnessMeter {
+    sample_rate : f64,
+    // One filter for each channel, made when we see how many there are
+    filters : Vec<KWeighting>,
+    step_len : usize,
+    step_samples : usize,
+    step_energy : f64,
+    // Energy of the most recent steps, enough to make up a block
+    steps : VecDeque<f64>,
+    // Mean power of every block we're measuring over
+    blocks : VecDeque<f64>,
+    // How many blocks to measure over, None for all of them
+    max_blocks : Option<usize>,
+    // Total power and number of the blocks that pass the absolute gate
+    audible_power : f64,
+    audible_blocks : usize,
+    // Kept up to date as blocks end when measuring over a window
+    windowed : Option<f64>,
+}
+
+impl LoudnessMeter {
+    // Measures everything, the loudness is worked out when it's asked for
+    // so this is for finding the loudness of a whole song at the end
+    pub fn new(sample_rate : f64) -> Self {
+        LoudnessMeter {
+            sample_rate : sample_rate,
+            filters : Vec::new(),
+            step_len : ((sample_rate * BLOCK_SECONDS / BLOCK_STEPS as f64) as usize).max(1),
+            step_samples : 0,
+            step_energy : 0.0,
+            steps : VecDeque::new(),
+            blocks : VecDeque::new(),
+            max_blocks : None,
+            audible_power : 0.0,
+            audible_blocks : 0,
+            windowed : None,
+        }
+    }
+
+    // Measures the last few seconds, for live audio that could go on forever
+    pub fn windowed(sample_rate : f64, seconds : f64) -> Self {
+        let mut meter = LoudnessMeter::new(sample_rate);
+        let blocks_per_second = sample_rate / (meter.step_len as f64);
+        meter.max_blocks = Some(((seconds * blocks_per_second) as usize).max(1));
+        meter
+    }
+
+    // Add a frame of samples, one for each channel
+    pub fn process(&mut self, samples : &[f32]) {
+        while self.filters.len() < samples.len() {
+            self.filters.push(KWeighting::new(self.sample_rate));
+        }
+
+        for (filter, &s) in self.filters.iter_mut().zip(samples.iter()) {
+            let y = filter.process(s as f64);
+            self.step_energy += y * y;
+        }
+
+        self.step_samples += 1;
+        if self.step_samples == self.step_len {
+            self.end_step();
+        }
+    }
+
+    fn end_step(&mut self) {
+        self.steps.push_back(self.step_energy);
+        if self.steps.len() > BLOCK_STEPS {
+            self.steps.pop_front();
+        }
+        self.step_energy = 0.0;
+        self.step_samples = 0;
+
+        if self.steps.len() == BLOCK_STEPS {
+            let energy = self.steps.iter().fold(0.0, |acc, e| acc + e);
+            self.push_block(energy / (BLOCK_STEPS * self.step_len) as f64);
+        }
+    }
+
+    fn push_block(&mut self, power : f64) {
+        self.blocks.push_back(power);
+        if loudness(power) > ABSOLUTE_GATE {
+            self.audible_power += power;
+            self.audible_blocks += 1;
+        }
+
+        if let Some(max) = self.max_blocks {
+            if self.blocks.len() > max {
+                let old = self.blocks.pop_front().unwrap();
+                if loudness(old) > ABSOLUTE_GATE {
+                    self.audible_power -= old;
+                    self.audible_blocks -= 1;
+                }
+                // Don't let rounding errors build up in the total
+                if self.audible_blocks == 0 {
+                    self.audible_power = 0.0;
+                }
+            }
+            self.windowed = self.gated_loudness();
+        }
+    }
+
+    // None until there has been at least one block that wasn't silent
+    pub fn integrated(&self) -> Option<f64> {
+        match self.max_blocks {
+            Some(_) => self.windowed,
+            None => self.gated_loudness(),
+        }
+    }
+
+    fn gated_loudness(&self) -> Option<f64> {
+        if self.audible_blocks == 0 {
+            return None;
+        }
+
+        let gate = loudness(self.audible_power / self.audible_blocks as f64) + RELATIVE_GATE;
+        let (power, count) = self.blocks.iter()
+                                        .filter(|&&p| loudness(p) > ABSOLUTE_GATE && loudness(p) > gate)
+                                        .fold((0.0, 0), |(sum, n), &p| (sum + p, n + 1));
+        Some(loudness(power / count as f64))
+    }
+}
+
+// Loudness in LUFS of a mean square power
+fn loudness(power : f64) -> f64 {
+    -0.691 + 10.0 * power.max(1e-20).log10()
+}
@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// Until a file says otherwise there are 120 beats a minute
+const DEFAULT_TEMPO : u32 = 500_000;
+
+// Notes count for this long after they start even if they've already ended,
+// so ones shorter than the gap between updates aren't missed
+const NOTE_CATCH_SECONDS : f64 = 0.05;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Message {
+    // Note number and velocity from 0 to 1
+    NoteOn(u8, f64),
+    NoteOff(u8),
+    // Controller number and value from 0 to 1
+    Control(u8, f64),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Event {
+    // Seconds from the start of the song
+    time : f64,
+    // 0 to 15
+    channel : u8,
+    message : Message,
+}
+
+// The notes and controllers of a Standard MIDI File, in the order they happen
+pub struct Midi {
+    events : Vec<Event>,
+}
+
+impl Midi {
+    // A MIDI file next to the song with the same name, like song.mid for
+    // song.wav, or song.wav.mid
+    pub fn find_for(song : &Path) -> Option<Self> {
+        if !song.is_file() {
+            return None;
+        }
+
+        let mut sidecar = song.as_os_str().to_owned();
+        sidecar.push(".mid");
+        let candidates = [song.with_extension("mid"), song.with_extension("midi"), PathBuf::from(sidecar)];
+
+        candidates.iter().find(|p| p.is_file()).and_then(|path| {
+            match Midi::open(path) {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    println!("Error: couldn't read {}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+    }
+
+    pub fn open(path : &Path) -> Result<Self, String> {
+        let mut bytes = Vec::new();
+        File::open(path).and_then(|mut f| f.read_to_end(&mut bytes))
+                        .map_err(|e| e.to_string())?;
+        Midi::parse(&bytes)
+    }
+
+    fn parse(bytes : &[u8]) -> Result<Self, String> {
+        let mut file = Reader::new(bytes);
+        if file.take(4)? != b"MThd" {
+            return Err("not a MIDI file".to_string());
+        }
+        let header_len = file.u32()? as usize;
+        let header = file.take(header_len)?;
+        if header.len() < 6 {
+            return Err("header is too short".to_string());
+        }
+        let division = (header[4] as u16) << 8 | header[5] as u16;
+
+        // Every track's events with the tick they're on, merged into one list
+        let mut ticked = Vec::new();
+        while !file.at_end() {
+            let id = file.take(4)?;
+            let len = file.u32()? as usize;
+            let chunk = file.take(len)?;
+            // Anything else is an extension we don't know about
+            if id == b"MTrk" {
+                read_track(chunk, &mut ticked)?;
+            }
+        }
+        // Stable, so events on the same tick keep the order they were written in
+        ticked.sort_by_key(|&(tick, _)| tick);
+
+        let events = if division & 0x8000 == 0 {
+            // Ticks are a fraction of a beat, so the tempo changes how long they are
+            let per_beat = division.max(1) as f64;
+            let mut tempo = DEFAULT_TEMPO;
+            let mut last_tick = 0;
+            let mut time = 0.0;
+            let mut events = Vec::new();
+            for (tick, item) in ticked {
+                time += (tick - last_tick) as f64 * tempo as f64 / 1e6 / per_beat;
+                last_tick = tick;
+                match item {
+                    Item::Tempo(x) => tempo = x,
+                    Item::Event(channel, message) => events.push(Event {
+                        time : time,
+                        channel : channel,
+                        message : message,
+                    }),
+                }
+            }
+            events
+        }
+        else {
+            // Ticks are a fraction of an SMPTE frame, 29 means 29.97 frames a second
+            let fps = match ((division >> 8) as u8 as i8).wrapping_neg() as u8 {
+                29 => 29.97,
+                x => x as f64,
+            };
+            let per_frame = (division & 0xFF).max(1) as f64;
+            ticked.into_iter().filter_map(|(tick, item)| match item {
+                Item::Event(channel, message) => Some(Event {
+                    time : tick as f64 / (fps * per_frame),
+                    channel : channel,
+                    message : message,
+                }),
+                Item::Tempo(_) => None,
+            }).collect()
+        };
+
+        Ok(Midi {
+            events : events,
+        })
+    }
+}
+
+enum Item {
+    // Microseconds per beat
+    Tempo(u32),
+    Event(u8, Message),
+}
+
+fn read_track(chunk : &[u8], ticked : &mut Vec<(u64, Item)>) -> Result<(), String> {
+    let mut track = Reader::new(chunk);
+    let mut tick = 0;
+    let mut running_status = None;
+
+    while !track.at_end() {
+        tick += track.vlq()? as u64;
+
+        let status = if track.peek()? & 0x80 != 0 {
+            track.byte()?
+        }
+        else {
+            running_status.ok_or("data without a status byte")?
+        };
+
+        match status {
+            // Meta events, of which we only need the tempo
+            0xFF => {
+                running_status = None;
+                let kind = track.byte()?;
+                let len = track.vlq()? as usize;
+                let data = track.take(len)?;
+                match kind {
+                    0x51 if len == 3 => {
+                        let tempo = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+                        ticked.push((tick, Item::Tempo(tempo)));
+                    }
+                    0x2F => break,
+                    _ => {}
+                }
+            }
+            // System exclusive
+            0xF0 | 0xF7 => {
+                running_status = None;
+                let len = track.vlq()? as usize;
+                track.take(len)?;
+            }
+            // System common and real time messages have no place in a file,
+            // and we couldn't tell how long they are to skip them
+            0xF1 ... 0xFE => {
+                return Err(format!("unexpected status byte {:#X}", status));
+            }
+            _ => {
+                running_status = Some(status);
+                let channel = status & 0x0F;
+                let message = match status & 0xF0 {
+                    0x80 => {
+                        let note = track.byte()?;
+                        track.byte()?;
+                        Some(Message::NoteOff(note))
+                    }
+                    0x90 => {
+                        let note = track.byte()?;
+                        // A note on with no velocity is how a lot of files end notes
+                        match track.byte()? {
+                            0 => Some(Message::NoteOff(note)),
+                            v => Some(Message::NoteOn(note, v as f64 / 127.0)),
+                        }
+                    }
+                    0xB0 => {
+                        let number = track.byte()?;
+                        let value = track.byte()?;
+                        Some(Message::Control(number, value as f64 / 127.0))
+                    }
+                    // Program changes and channel pressure
+                    0xC0 | 0xD0 => {
+                        track.byte()?;
+                        None
+                    }
+                    // Key pressure and pitch bend
+                    _ => {
+                        track.take(2)?;
+                        None
+                    }
+                };
+                if let Some(m) = message {
+                    ticked.push((tick, Item::Event(channel, m)));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Reads through a chunk, running out of bytes is an error
+struct Reader<'a> {
+    bytes : &'a [u8],
+    pos : usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes : &'a [u8]) -> Self {
+        Reader {
+            bytes : bytes,
+            pos : 0,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn take(&mut self, n : usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.pos < n {
+            return Err("file ends early".to_string());
+        }
+        let x = &self.bytes[self.pos .. self.pos + n];
+        self.pos += n;
+        Ok(x)
+    }
+
+    fn peek(&self) -> Result<u8, String> {
+        self.bytes.get(self.pos).cloned().ok_or("file ends early".to_string())
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        self.take(1).map(|x| x[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        self.take(4).map(|x| (x[0] as u32) << 24 | (x[1] as u32) << 16 | (x[2] as u32) << 8 | x[3] as u32)
+    }
+
+    // Seven bits a byte, the top bit says there's more to come
+    fn vlq(&mut self) -> Result<u32, String> {
+        let mut x = 0;
+        for _ in 0 .. 4 {
+            let b = self.byte()?;
+            x = x << 7 | (b & 0x7F) as u32;
+            if b & 0x80 == 0 {
+                return Ok(x);
+            }
+        }
+        Err("variable length number is too long".to_string())
+    }
+}
+
+// Follows a MIDI file along with the song
+pub struct MidiPlayer<'a> {
+    midi : &'a Midi,
+    // The first event we haven't got to yet
+    next : usize,
+    // Seconds we've got up to
+    time : f64,
+    held : HashMap<(u8, u8), f64>,
+    // Values with the event that set them, so the latest on any channel can be found
+    controls : HashMap<(u8, u8), (usize, f64)>,
+}
+
+impl<'a> MidiPlayer<'a> {
+    pub fn new(midi : &'a Midi) -> Self {
+        MidiPlayer {
+            midi : midi,
+            next : 0,
+            time : 0.0,
+            held : HashMap::new(),
+            controls : HashMap::new(),
+        }
+    }
+
+    // Move on to time seconds into the song, returning the notes sounding
+    // and where the controllers are
+    pub fn advance(&mut self, time : f64) -> MidiFrame {
+        // Going backwards means playing through again from the start
+        if time < self.time {
+            *self = MidiPlayer::new(self.midi);
+        }
+        self.time = time;
+
+        let mut notes = Vec::new();
+        while let Some(event) = self.midi.events.get(self.next) {
+            if event.time > time {
+                break;
+            }
+            self.next += 1;
+
+            let channel = event.channel;
+            match event.message {
+                Message::NoteOn(note, velocity) => {
+                    self.held.insert((channel, note), velocity);
+                    if time - event.time < NOTE_CATCH_SECONDS {
+                        notes.push((channel, note, velocity));
+                    }
+                }
+                Message::NoteOff(note) => {
+                    self.held.remove(&(channel, note));
+                }
+                Message::Control(number, value) => {
+                    self.controls.insert((channel, number), (self.next, value));
+                }
+            }
+        }
+
+        notes.extend(self.held.iter().map(|(&(c, n), &v)| (c, n, v)));
+        MidiFrame {
+            notes : notes,
+            controls : self.controls.iter().map(|(&(c, n), &(order, v))| (c, n, order, v)).collect(),
+        }
+    }
+}
+
+// The MIDI for one point in the song
+#[derive(Clone, Debug, Default)]
+pub struct MidiFrame {
+    // Channel, note and velocity
+    notes : Vec<(u8, u8, f64)>,
+    // Channel, controller number, when it was set and value
+    controls : Vec<(u8, u8, usize, f64)>,
+}
+
+// Channels are numbered 1 to 16 like on instruments, 0 matches any of them
+fn on_channel(c : u8, channel : i64) -> bool {
+    channel == 0 || c as i64 + 1 == channel
+}
+
+impl MidiFrame {
+    // Velocity of the loudest note from lo to hi, 0 if there isn't one
+    pub fn velocity(&self, channel : i64, lo : i64, hi : i64) -> f64 {
+        self.notes.iter()
+                  .filter(|&&(c, n, _)| on_channel(c, channel) && n as i64 >= lo && n as i64 <= hi)
+                  .fold(0.0, |acc, &(_, _, v)| acc.max(v))
+    }
+
+    // Latest value of a controller, 0 before it's been set. On channel 0
+    // that's from whichever channel changed it last.
+    pub fn control(&self, channel : i64, number : i64) -> f64 {
+        self.controls.iter()
+                     .filter(|&&(c, n, _, _)| on_channel(c, channel) && n as i64 == number)
+                     .max_by_key(|&&(_, _, order, _)| order)
+                     .map(|&(_, _, _, v)| v)
+                     .unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Midi, MidiPlayer, Message};
+
+    // A format 1 file with the given division and tracks
+    fn smf(division : u16, tracks : &[&[u8]]) -> Vec<u8> {
+        let mut file = b"MThd\x00\x00\x00\x06\x00\x01".to_vec();
+        file.extend_from_slice(&[0, tracks.len() as u8, (division >> 8) as u8, division as u8]);
+        for track in tracks.iter() {
+            let len = track.len();
+            file.extend_from_slice(b"MTrk");
+            file.extend_from_slice(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]);
+            file.extend_from_slice(track);
+        }
+        file
+    }
+
+    fn close(a : f64, b : f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn running_status() {
+        // The second and third notes leave out the status byte
+        let track = [0x00, 0x91, 60, 64, 0x60, 62, 127, 0x00, 64, 1, 0x00, 0xFF, 0x2F, 0x00];
+        let midi = Midi::parse(&smf(96, &[&track])).unwrap();
+        let notes = midi.events.iter().map(|e| (e.channel, e.message)).collect::<Vec<_>>();
+        assert_eq!(notes, vec![(1, Message::NoteOn(60, 64.0 / 127.0)),
+                               (1, Message::NoteOn(62, 1.0)),
+                               (1, Message::NoteOn(64, 1.0 / 127.0))]);
+        assert!(close(midi.events[1].time, 0.5));
+    }
+
+    #[test]
+    fn tempo_change() {
+        // Half a second a beat to start with, then a second a beat
+        let tempo = [0x60, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40, 0x00, 0xFF, 0x2F, 0x00];
+        let notes = [0x60, 0x90, 60, 64, 0x60, 0x80, 60, 0, 0x00, 0xFF, 0x2F, 0x00];
+        let midi = Midi::parse(&smf(96, &[&tempo, &notes])).unwrap();
+        assert!(close(midi.events[0].time, 0.5));
+        assert!(close(midi.events[1].time, 1.5));
+    }
+
+    #[test]
+    fn note_on_without_velocity() {
+        let track = [0x00, 0x90, 60, 100, 0x10, 0x90, 60, 0];
+        let midi = Midi::parse(&smf(96, &[&track])).unwrap();
+        assert_eq!(midi.events[1].message, Message::NoteOff(60));
+    }
+
+    #[test]
+    fn smpte_division() {
+        // 25 frames a second of 40 ticks, so 1000 ticks is a second
+        let track = [0x00, 0xB0, 7, 127, 0x87, 0x68, 0xB0, 7, 0];
+        let midi = Midi::parse(&smf(0xE728, &[&track])).unwrap();
+        assert!(close(midi.events[0].time, 0.0));
+        assert!(close(midi.events[1].time, 1.0));
+    }
+
+    #[test]
+    fn stray_status() {
+        let track = [0x00, 0x90, 60, 100, 0x00, 0xF8, 0x00, 61, 100];
+        assert!(Midi::parse(&smf(96, &[&track])).is_err());
+    }
+
+    #[test]
+    fn latest_control() {
+        // Channel 0 follows whichever channel moved last, even if it went down
+        let track = [0x00, 0xB0, 7, 127, 0x60, 0xB1, 7, 0];
+        let midi = Midi::parse(&smf(96, &[&track])).unwrap();
+        let mut player = MidiPlayer::new(&midi);
+        assert!(close(player.advance(0.25).control(0, 7), 1.0));
+        let frame = player.advance(1.0);
+        assert!(close(frame.control(0, 7), 0.0));
+        assert!(close(frame.control(1, 7), 1.0));
+    }
+}
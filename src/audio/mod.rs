@@ -1,96 +1,615 @@
-use std::collections::{LinkedList, HashMap};
+use std::collections::HashMap;
 use std::io::Read;
-use std::time::{Duration, SystemTime};
-use std::thread::sleep;
-use std::sync::mpsc::Sender;
-use std::path::Path;
+use std::time::Duration;
+use std::sync::Arc;
+use std::sync::mpsc::{Sender, Receiver, channel};
+use std::thread;
+use std::path::{Path, PathBuf};
 use std::fs::File;
-use common::{AudioType, AudioPacket, AudioUpdate};
+use std::io::BufReader;
+use common::{AudioType, AudioPacket, AudioUpdate, AudioFrame, AudioSettings, TransportPacket};
+use clock::Clock;
 use hound::{Sample, WavReader};
+use self::spectrum::{Spectrum, SpectrumAnalyser, FrequencyBands};
+use self::beat::BeatTracker;
+use self::playback::{Playback, DecodedAudio};
+use self::transport::Transport;
+use self::analysis::Analysis;
+use self::playlist::Playlist;
+use self::loudness::LoudnessMeter;
+use self::normalise::Normaliser;
+use self::envelope::EnvelopeFollower;
+use self::timbre::Timbre;
+use self::pitch::PitchTracker;
+use self::tags::Tags;
+use self::midi::{Midi, MidiPlayer, MidiFrame};
 
 pub mod mp3;
 pub mod wav;
+pub mod flac;
+pub mod ogg;
+pub mod pcm;
+pub mod synth;
+pub mod playlist;
+pub mod tags;
+pub mod midi;
+pub mod transport;
+pub mod analysis;
+pub mod export;
+pub mod loudness;
+pub mod normalise;
+pub mod envelope;
+pub mod structure;
+pub mod timbre;
+pub mod pitch;
+pub mod fft;
+pub mod spectrum;
+pub mod beat;
+pub mod playback;
+
+// How many updates we send to the mapper every second
+const UPDATES_PER_SECOND : f64 = 110.0;
+
+// Levels are scaled by this so typical music sits roughly in 0..1
+const LEVEL_GAIN : f64 = 2.0;
+
+// Length of audio each FFT covers, rounded to a power of two number of samples
+const FFT_SECONDS : f64 = 0.046;
+
+// Live input can't wait to see into the future, so it gets a much shorter
+// window to keep the delay down
+pub const LIVE_SAMPLE_TIME : f64 = 0.05;
+
+// Live audio could go on forever, so its loudness is measured over this
+// many seconds and follows the music as it changes
+const LIVE_LOUDNESS_SECONDS : f64 = 30.0;
 
 pub trait Song : Iterator<Item=AudioData>{
-    fn sample_max_value(&self) -> u32;
-    fn play(&self);
+    fn sample_rate(&self) -> u32;
+    // A copy of the whole song from the start to send to the speakers,
+    // None if it can't be played
+    fn decode(&self) -> Option<DecodedAudio>;
+
+    // Start playing the song, returns a clock that follows what has been
+    // played so far if it could be played
+    fn play(&self) -> Option<Playback> {
+        self.decode().and_then(playback::play)
+    }
+
+    // Live songs are heard as they arrive, so we analyse them as they come
+    // in instead of ahead of time
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    // Carry on from the given point in the song. Songs analysed ahead of time
+    // are seeked by moving the clock instead, so only live songs need this,
+    // and then only if they aren't coming down a pipe.
+    fn seek(&mut self, _time : Duration) -> Result<(), String> {
+        Err("This audio can't be seeked".to_string())
+    }
 }
 
+// A single frame of audio, one sample for each channel
+// Samples are normalised to -1..1 whatever the source format
 pub struct AudioData {
-    pub sample : i32,
+    pub samples : Vec<f32>,
     pub time : Duration,
 }
 
-pub fn make_song(path : &Path, start_time : SystemTime) -> Option<Box<Song<Item=AudioData>>> {
-    path.extension().and_then(|ext| {
-        match ext.to_str().unwrap().to_lowercase().as_ref() {
-            "wav" => {
-                let file = File::open(path).unwrap();
-                let wav = wav::WavSong::new(file, path).unwrap();
-                let x : Box<Song<Item=AudioData>> = Box::new(wav);
-                Some(x)
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Wav,
+    Mp3,
+    Flac,
+    Ogg,
+}
+
+pub fn make_song(path : &Path) -> Option<Box<Song<Item=AudioData>>> {
+    // Raw audio from stdin or a pipe, see PcmSong::from_spec
+    if let Some(spec) = path.to_str().and_then(|p| if p.starts_with("pcm:") { Some(&p[4 ..]) } else { None }) {
+        return match pcm::PcmSong::from_spec(spec) {
+            Ok(x) => Some(Box::new(x)),
+            Err(e) => {
+                println!("Error: {}", e);
+                None
             }
-            "mp3" => {
-                let file = File::open(path).unwrap();
-                let mp3 = mp3::Mp3Song::new(file, start_time);
-                let x : Box<Song<Item=AudioData>> = Box::new(mp3);
-                Some(x)
+        };
+    }
+
+    // Generated test signals, see SynthSong::from_spec
+    if let Some(spec) = path.to_str().and_then(|p| if p.starts_with("synth:") { Some(&p[6 ..]) } else { None }) {
+        return match synth::SynthSong::from_spec(spec) {
+            Ok(x) => Some(Box::new(x)),
+            Err(e) => {
+                println!("Error: {}", e);
+                None
             }
+        };
+    }
+
+    let from_extension = path.extension().and_then(|ext| {
+        let ext = ext.to_str().unwrap().to_lowercase();
+        match ext.as_ref() {
+            "wav" => Some(Format::Wav),
+            "mp3" => Some(Format::Mp3),
+            "flac" => Some(Format::Flac),
+            "ogg" | "oga" => Some(Format::Ogg),
             x => {
-                println!("Error: unsupported file extension \"{}\"", x);
+                println!("Unknown file extension \"{}\", checking contents", x);
                 None
             }
         }
-    })
+    });
+
+    // Trust the contents of the file over its name
+    let format = match (sniff_format(path), from_extension) {
+        (Some(sniffed), Some(ext)) => {
+            if sniffed != ext {
+                println!("File extension says {:?} but it looks like {:?}", ext, sniffed);
+            }
+            sniffed
+        }
+        (Some(sniffed), None) => sniffed,
+        (None, Some(ext)) => ext,
+        (None, None) => {
+            println!("Error: unsupported file type");
+            return None;
+        }
+    };
+
+    let file = match File::open(path) {
+        Ok(x) => BufReader::new(x),
+        Err(e) => {
+            println!("Error: couldn't open {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    let song : Result<Box<Song<Item=AudioData>>, String> = match format {
+        Format::Wav => {
+            wav::WavSong::new(file, path)
+                .map(|x| Box::new(x) as Box<Song<Item=AudioData>>)
+                .map_err(|e| format!("{:?}", e))
+        }
+        Format::Mp3 => {
+            mp3::Mp3Song::new(file, path)
+                .map(|x| Box::new(x) as Box<Song<Item=AudioData>>)
+                .map_err(|e| format!("{:?}", e))
+        }
+        Format::Flac => {
+            flac::FlacSong::new(file, path)
+                .map(|x| Box::new(x) as Box<Song<Item=AudioData>>)
+                .map_err(|e| format!("{:?}", e))
+        }
+        Format::Ogg => {
+            ogg::OggSong::new(file, path)
+                .map(|x| Box::new(x) as Box<Song<Item=AudioData>>)
+                .map_err(|e| format!("{:?}", e))
+        }
+    };
+
+    match song {
+        Ok(x) => Some(x),
+        Err(e) => {
+            println!("Error: couldn't read {:?} file: {}", format, e);
+            None
+        }
+    }
+}
+
+// Work out the format from the first few bytes of the file
+fn sniff_format(path : &Path) -> Option<Format> {
+    let mut header = [0u8; 12];
+    let read = File::open(path).and_then(|mut f| f.read(&mut header)).unwrap_or(0);
+    let header = &header[.. read];
+
+    if header.len() >= 12 && &header[0 .. 4] == b"RIFF" && &header[8 .. 12] == b"WAVE" {
+        Some(Format::Wav)
+    }
+    else if header.starts_with(b"fLaC") {
+        Some(Format::Flac)
+    }
+    else if header.starts_with(b"OggS") {
+        Some(Format::Ogg)
+    }
+    // Either an ID3 tag or straight into an mpeg frame sync
+    else if header.starts_with(b"ID3") || (header.len() >= 2 && header[0] == 0xFF && header[1] & 0xE0 == 0xE0) {
+        Some(Format::Mp3)
+    }
+    else {
+        None
+    }
+}
+
+// Analyse a song ahead of time, using the cached analysis next to it if there
+// is one, returns None for live songs which can only be analysed as they arrive
+pub fn analyse_song(song : &mut Song<Item=AudioData>, path : &Path, sample_time : f64) -> Option<Analysis> {
+    if song.is_live() {
+        None
+    }
+    // Generated songs have no file to keep the analysis next to
+    else if !path.is_file() {
+        Some(Analysis::analyse(song, sample_time))
+    }
+    else {
+        Some(analysis::load_or_analyse(song, path, sample_time))
+    }
 }
 
 pub fn run_audio(
-    mut song : Box<Song<Item=AudioData>>,
+    song : Box<Song<Item=AudioData>>,
+    analysis : Option<Analysis>,
+    playlist : Playlist,
+    sample_time : f64,
+    settings : AudioSettings,
     tx : Sender<AudioPacket>,
+    transport_rx : Receiver<TransportPacket>,
+    song_tx : Sender<PathBuf>,
+    clock : Arc<Clock>,
+    output : Option<Playback>,
+    ) {
+    let mut transport = Transport::new(transport_rx, clock.clone());
+    match analysis {
+        Some(analysis) => {
+            play_playlist(analysis, playlist, sample_time, settings, &tx, &mut transport, &song_tx, clock, output);
+        }
+        None => {
+            run_live(song, settings, &tx, &mut transport, &*clock);
+        }
+    }
+}
+
+// A song from the playlist ready to play, with where it starts on the clock
+// if it has been queued on the output
+struct Prepared {
+    analysis : Analysis,
+    tags : Tags,
+    midi : Option<Midi>,
+    start : Option<Duration>,
+}
+
+// Play the first song, which is already playing, and each one after it in
+// the playlist, getting the next one ready while the current one plays
+fn play_playlist(
+    analysis : Analysis,
+    playlist : Playlist,
     sample_time : f64,
-    start_time : SystemTime
+    mut settings : AudioSettings,
+    tx : &Sender<AudioPacket>,
+    transport : &mut Transport,
+    song_tx : &Sender<PathBuf>,
+    clock : Arc<Clock>,
+    output : Option<Playback>,
     ) {
-    song.play();
+    let playlist = Arc::new(playlist);
+    let mut current = analysis;
+    let mut tags = Tags::for_display(playlist.get(0));
+    let mut midi = Midi::find_for(playlist.get(0));
+    let mut start = Duration::new(0, 0);
+    let mut index = 0;
+    let mut next_index = 1;
+    let mut track = 0;
+
+    loop {
+        let (prepared_tx, prepared_rx) = channel();
+        let (next_playlist, next_output) = (playlist.clone(), output.clone());
+        thread::spawn(move || {
+            let _ = prepared_tx.send(prepare_next(&next_playlist, next_index, sample_time, next_output));
+        });
+
+        try_send_update(tx, AudioPacket::Song(tags));
+
+        let song = PlayingSong {
+            analysis : &current,
+            midi : midi.as_ref(),
+            track : track,
+            index : index,
+            songs : playlist.len(),
+            start : start,
+        };
+        transport.set_song(start, current.duration());
+        play_analysis(&song, &mut settings, tx, transport, &*clock);
+
+        let prepared = match prepared_rx.recv() {
+            Ok(Some((i, x))) => {
+                index = i;
+                x
+            }
+            _ => break,
+        };
+
+        let end = start + current.duration();
+        start = match prepared.start {
+            Some(x) => x,
+            None => {
+                // Nothing is playing to keep us waiting, so make up for however
+                // long it took to get the next song ready
+                if clock.position() > end {
+                    clock.seek(end);
+                }
+                end
+            }
+        };
+        current = prepared.analysis;
+        tags = prepared.tags;
+        midi = prepared.midi;
+        next_index = index + 1;
+        track += 1;
+
+        // Nobody might be listening if the script doesn't change between songs
+        let _ = song_tx.send(playlist.get(index).to_path_buf());
+    }
+}
 
-    let sample_max = song.sample_max_value();
-    let mut audio_proc = AudioProcessor::new(tx, sample_time, start_time, sample_max);
+// Analyse the first song in the playlist from index that we can, and queue
+// it to play straight after what's queued so far.
+// Returns its index in the playlist.
+fn prepare_next(playlist : &Playlist, index : usize, sample_time : f64, output : Option<Playback>) -> Option<(usize, Prepared)> {
+    for i in index .. playlist.len() {
+        let path = playlist.get(i);
+        let mut song = match make_song(path) {
+            Some(x) => x,
+            None => {
+                println!("Skipping {}", path.display());
+                continue;
+            }
+        };
 
-    for (i, data) in song.enumerate() {
-        audio_proc.process_sample(data.sample, data.time);
+        let analysis = match analyse_song(&mut *song, path, sample_time) {
+            Some(x) => x,
+            None => {
+                println!("Skipping {}, live audio can't be part of a playlist", path.display());
+                continue;
+            }
+        };
+
+        let start = match (output.as_ref(), song.decode()) {
+            (Some(output), Some(audio)) => Some(output.queue(audio)),
+            _ => None,
+        };
+
+        return Some((i, Prepared {
+            analysis : analysis,
+            tags : Tags::for_display(path),
+            midi : Midi::find_for(path),
+            start : start,
+        }));
     }
+    None
 }
 
-struct AudioProcessor {
-    window : TimeWindow<i32>,
-    tx : Sender<AudioPacket>,
-    start_time : SystemTime,
-    sample_max : u32,
+// A song being played as part of the playlist
+struct PlayingSong<'a> {
+    analysis : &'a Analysis,
+    // Played along with the song if it has one
+    midi : Option<&'a Midi>,
+    // How many songs came before it
+    track : usize,
+    // Where it is in the playlist, and how many songs the playlist has
+    index : usize,
+    songs : usize,
+    // Where it starts on the clock
+    start : Duration,
+}
+
+// Send the analysed frames as the listener reaches them
+fn play_analysis(song : &PlayingSong, settings : &mut AudioSettings, tx : &Sender<AudioPacket>, transport : &mut Transport, clock : &Clock) {
+    let analysis = song.analysis;
+    let song_normaliser = |settings : &AudioSettings| {
+        Normaliser::for_song(settings.normalisation, analysis.peak(AudioType::Level), analysis.loudness())
+    };
+    let mut normaliser = song_normaliser(settings);
+    let mut envelopes = EnvelopeFollower::new(settings.envelopes.clone());
+    let mut midi = song.midi.map(MidiPlayer::new);
+
+    let mut last_sent = None;
+    loop {
+        // The script changing can't change the analysis, only what we do with it
+        if let Some(new_settings) = transport.poll(tx) {
+            *settings = new_settings;
+            normaliser = song_normaliser(settings);
+            envelopes = EnvelopeFollower::new(settings.envelopes.clone());
+            last_sent = None;
+        }
+
+        // Playback stops the clock at the end of the audio, which can be
+        // part way through the last frame
+        let position = clock.position().checked_sub(song.start).unwrap_or(Duration::new(0, 0));
+        let i = analysis.index_at(position);
+        if position >= analysis.duration() || i >= analysis.len() {
+            break;
+        }
+
+        // The clock can jump about, so only skip sending if we haven't moved
+        if last_sent != Some(i) {
+            let time = song.start + analysis.time_of(i);
+            let mut frame = analysis.frame(i);
+            normaliser.apply(&mut frame.components);
+            envelopes.apply(&mut frame.components, time);
+            // The last frame is the end of the song, so progress gets all the way to 1
+            let progress = if analysis.len() > 1 { i as f64 / (analysis.len() - 1) as f64 } else { 0.0 };
+            frame.components.insert(AudioType::TrackIndex, song.track as f64);
+            frame.components.insert(AudioType::TrackProgress, progress);
+            frame.components.insert(AudioType::Time, seconds(analysis.time_of(i)));
+            frame.components.insert(AudioType::Duration, seconds(analysis.duration()));
+            // Each song of the playlist counts the same however long it is,
+            // since the ones to come haven't been analysed yet
+            frame.components.insert(AudioType::Progress, (song.index as f64 + progress) / song.songs.max(1) as f64);
+            if let Some(ref mut player) = midi {
+                frame.midi = player.advance(seconds(analysis.time_of(i)));
+            }
+            let update = AudioPacket::Update(AudioUpdate {
+                time : time,
+                audio : frame,
+            });
+            try_send_update(tx, update);
+            last_sent = Some(i);
+        }
+
+        clock.wait_until(song.start + analysis.time_of(i + 1).min(analysis.duration()));
+    }
+}
+
+// Analyse audio as it arrives and send the results straight away
+fn run_live(mut song : Box<Song<Item=AudioData>>, settings : AudioSettings, tx : &Sender<AudioPacket>, transport : &mut Transport, clock : &Clock) {
+    let sample_rate = song.sample_rate() as f64;
+    let mut audio_proc = AudioProcessor::live(sample_rate, FrequencyBands::default());
+    let mut normaliser = Normaliser::running(settings.normalisation, audio_proc.update_rate());
+    let mut envelopes = EnvelopeFollower::new(settings.envelopes);
+    // Where the audio processor started from
+    let mut start = Duration::new(0, 0);
+
+    while let Some(data) = song.next() {
+        if let Some(settings) = transport.poll(tx) {
+            normaliser = Normaliser::running(settings.normalisation, audio_proc.update_rate());
+            envelopes = EnvelopeFollower::new(settings.envelopes);
+        }
+
+        if let Some(time) = transport.take_live_seek() {
+            match song.seek(time) {
+                Ok(()) => {
+                    // The windows shouldn't mix audio from either side of the
+                    // jump, the envelopes notice it for themselves
+                    audio_proc = AudioProcessor::live(sample_rate, FrequencyBands::default());
+                    start = time;
+                    clock.seek(time);
+                    try_send_update(tx, AudioPacket::Reset);
+                    continue;
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        if let Some(mut update) = audio_proc.process_sample(&data.samples) {
+            update.time += start;
+            normaliser.observe(&update.audio.components, audio_proc.loudness());
+            normaliser.apply(&mut update.audio.components);
+            envelopes.apply(&mut update.audio.components, update.time);
+
+            // Finding sections needs the whole song to look at
+            update.audio.components.insert(AudioType::Section, 0.0);
+            update.audio.components.insert(AudioType::SectionProgress, 0.0);
+            update.audio.components.insert(AudioType::TrackIndex, 0.0);
+            update.audio.components.insert(AudioType::TrackProgress, 0.0);
+            // There's no telling how long live audio will go on for
+            update.audio.components.insert(AudioType::Time, seconds(update.time));
+            update.audio.components.insert(AudioType::Duration, 0.0);
+            update.audio.components.insert(AudioType::Progress, 0.0);
+
+            // The audio is heard as it arrives, so the clock follows us
+            clock.seek(update.time);
+            try_send_update(tx, AudioPacket::Update(update));
+        }
+    }
+}
+
+// Works out the audio components of a stream of samples
+pub struct AudioProcessor {
+    // Window over the mono mix of all channels
+    window : TimeWindow<f32>,
+    left_window : TimeWindow<f32>,
+    right_window : TimeWindow<f32>,
+    // Window over the difference between the left and right channels
+    side_window : TimeWindow<f32>,
+    window_size : usize,
+    sample_rate : f64,
     impulse_triggered : bool,
     sample_number : usize,
+    samples_per_update : usize,
+    update_rate : f64,
+    spectrum : SpectrumAnalyser,
+    bands : FrequencyBands,
+    beat : BeatTracker,
+    timbre : Timbre,
+    pitch : PitchTracker,
+    // Held while there's no pitch so colours don't jump back to C
+    last_note : f64,
+    loudness : LoudnessMeter,
 }
 
 impl AudioProcessor {
-    fn new(tx : Sender<AudioPacket>, sample_time : f64, start_time : SystemTime, sample_max : u32) -> Self {
+    pub fn new(sample_time : f64, sample_rate : f64, bands : FrequencyBands) -> Self {
+
+        // The window sees sample_time into the past and the future, and the
+        // updates describe the middle of it
+        let window_size = ((sample_rate * sample_time) as usize).max(1);
+        let window = TimeWindow::new(window_size);
+
+        let samples_per_update = ((sample_rate / UPDATES_PER_SECOND).round() as usize).max(1);
+        let update_rate = sample_rate / samples_per_update as f64;
 
-        // For now we window over a half a second (Completely arbitrary)
-        // This is a quater of a second forwards and backwards in time
-        // Assume for now sample rate about 44k
-        let window_size = 44000.0 * sample_time;
-        let window = TimeWindow::new(window_size as usize);
         AudioProcessor {
-            tx : tx,
             window : window,
-            start_time : start_time,
+            left_window : TimeWindow::new(window_size),
+            right_window : TimeWindow::new(window_size),
+            side_window : TimeWindow::new(window_size),
+            window_size : window_size,
+            sample_rate : sample_rate,
             sample_number : 0,
-            sample_max : sample_max,
             impulse_triggered : false,
+            samples_per_update : samples_per_update,
+            update_rate : update_rate,
+            spectrum : SpectrumAnalyser::new(fft_size(sample_rate), sample_rate),
+            bands : bands,
+            beat : BeatTracker::new(update_rate),
+            timbre : Timbre::new(),
+            pitch : PitchTracker::new(sample_rate),
+            last_note : 0.0,
+            loudness : LoudnessMeter::new(sample_rate),
         }
     }
 
-    fn process_sample(&mut self, x : i32, time : Duration) {
+    // For audio analysed as it arrives
+    pub fn live(sample_rate : f64, bands : FrequencyBands) -> Self {
+        let mut audio_proc = AudioProcessor::new(LIVE_SAMPLE_TIME, sample_rate, bands);
+        audio_proc.loudness = LoudnessMeter::windowed(sample_rate, LIVE_LOUDNESS_SECONDS);
+        audio_proc
+    }
+
+    // How many updates come out for each second of audio
+    pub fn update_rate(&self) -> f64 {
+        self.update_rate
+    }
 
-        // Add the new sample to the window
+    // Integrated loudness in LUFS of everything so far, None if it was all silent
+    pub fn loudness(&self) -> Option<f64> {
+        self.loudness.integrated()
+    }
+
+    pub fn spectrum_centres(&self) -> &[f64] {
+        self.spectrum.centres()
+    }
+
+    // Feed the windows with zeros so the end of the song reaches the present,
+    // calling f with every update that comes out
+    pub fn flush<F : FnMut(AudioUpdate)>(&mut self, mut f : F) {
+        for _ in 0 .. self.window_size {
+            if let Some(update) = self.process_sample(&[0.0]) {
+                f(update);
+            }
+        }
+    }
+
+    // Add a frame of samples, returns an update every samples_per_update
+    // samples once the present of the windows has reached the song
+    pub fn process_sample(&mut self, samples : &[f32]) -> Option<AudioUpdate> {
+
+        // Mono files are treated as having identical left and right channels,
+        // anything past the first two channels only contributes to the mix
+        let left = samples[0];
+        let right = samples.get(1).cloned().unwrap_or(left);
+        let total = samples.iter().fold(0.0, |acc, &s| acc + s);
+        let x = total / samples.len() as f32;
+        let side = (left - right) / 2.0;
+
+        self.loudness.process(samples);
+
+        // Add the new samples to the windows
         self.window.step_forwards(x);
+        self.left_window.step_forwards(left);
+        self.right_window.step_forwards(right);
+        self.side_window.step_forwards(side);
 
         // Check if the new sample is significant
         let sig = self.window.current_significant();
@@ -99,51 +618,147 @@ impl AudioProcessor {
             self.impulse_triggered = true;
         }
 
-        // Arbitrary again
-        if (self.sample_number % 400 == 0) {
+        // The present of the windows lags the newest sample by the window size
+        let present = self.sample_number as i64 - self.window_size as i64;
+        self.sample_number += 1;
 
-            let mut audio_map: HashMap<AudioType, f64> = HashMap::new();
+        if present < 0 || present as usize % self.samples_per_update != 0 {
+            return None;
+        }
 
-            // If we are switiching to a new state
-            let impulse_intensity = if self.impulse_triggered {
-                x as f64 / (self.sample_max as f64)
-            }
-            else {
-                0.0
-            };
+        let mut audio_map: HashMap<AudioType, f64> = HashMap::new();
 
-            self.impulse_triggered = false;
-
-            audio_map.insert(AudioType::Impulse, impulse_intensity);
-
-            match self.start_time.elapsed()
-                            .ok()
-                            .and_then(|current_songtime| { 
-                                       time.checked_sub(current_songtime)
-                            })
-            {
-                Some(time_diff) => {
-
-                    // Sleep until the point in the song where we were triggered
-                    sleep(time_diff);
-
-                    let i = 5.0 * self.window.std_dev() / (self.sample_max as f64);
-                    //let level = x as f64 / (sample_max as f64);
-                    audio_map.insert(AudioType::Level, i);
-                    let update = AudioPacket::Update(AudioUpdate {
-                        time : time,
-                        audio : audio_map});
-                    try_send_update(&self.tx, update);
-                },
-                None => {
-                }
+        // If we are switiching to a new state
+        let impulse_intensity = if self.impulse_triggered {
+            self.window.present() as f64
+        }
+        else {
+            0.0
+        };
+
+        self.impulse_triggered = false;
+
+        audio_map.insert(AudioType::Impulse, impulse_intensity);
+
+        let spectrum = self.analyse_spectrum(&mut audio_map);
+
+        let chroma = pitch::chroma(&spectrum);
+        self.analyse_pitch(&mut audio_map);
+
+        let beat = self.beat.update(&spectrum);
+        audio_map.insert(AudioType::Beat, beat.beat);
+        audio_map.insert(AudioType::BeatPhase, beat.phase);
+        audio_map.insert(AudioType::Bpm, beat.bpm);
+
+        let i = LEVEL_GAIN * self.window.std_dev();
+        audio_map.insert(AudioType::Level, i);
+        self.insert_stereo(&mut audio_map);
+
+        Some(AudioUpdate {
+            time : from_seconds(present as f64 / self.sample_rate),
+            audio : AudioFrame {
+                components : audio_map,
+                spectrum : spectrum,
+                chroma : chroma,
+                midi : MidiFrame::default(),
+            }})
+    }
+
+    // Add Pitch in Hz, 0 if there isn't one, and the Note it's closest to
+    fn analyse_pitch(&mut self, audio_map : &mut HashMap<AudioType, f64>) {
+        let samples = self.window.around_present(self.pitch.size())
+                                 .iter()
+                                 .map(|&s| s as f64)
+                                 .collect::<Vec<f64>>();
+
+        let hz = match self.pitch.detect(&samples) {
+            Some(hz) => {
+                self.last_note = pitch::note_of(hz);
+                hz
             }
+            None => 0.0,
+        };
+
+        audio_map.insert(AudioType::Pitch, hz);
+        audio_map.insert(AudioType::Note, self.last_note);
+    }
+
+    fn insert_stereo(&self, audio_map : &mut HashMap<AudioType, f64>) {
+        let left = self.left_window.std_dev();
+        let right = self.right_window.std_dev();
+        let mid = self.window.std_dev();
+        let side = self.side_window.std_dev();
+
+        audio_map.insert(AudioType::LevelLeft, LEVEL_GAIN * left);
+        audio_map.insert(AudioType::LevelRight, LEVEL_GAIN * right);
+
+        // -1 is fully left, 1 fully right
+        let balance = if left + right > 0.0 {
+            (right - left) / (right + left)
         }
-        self.sample_number += 1;
+        else {
+            0.0
+        };
+        audio_map.insert(AudioType::Balance, balance);
+
+        // 0 for mono, 0.5 for uncorrelated channels, 1 for channels out of phase
+        let width = if mid + side > 0.0 {
+            side / (mid + side)
+        }
+        else {
+            0.0
+        };
+        audio_map.insert(AudioType::StereoWidth, width);
+    }
+
+    // Run the FFT over the samples around the present and add the
+    // frequency band components
+    fn analyse_spectrum(&mut self, audio_map : &mut HashMap<AudioType, f64>) -> Spectrum {
+        let samples = self.window.around_present(self.spectrum.size())
+                                 .iter()
+                                 .map(|&s| s as f64)
+                                 .collect::<Vec<f64>>();
+
+        let spectrum = self.spectrum.analyse(&samples);
+
+        let (bass_lo, bass_hi) = self.bands.bass;
+        let (mid_lo, mid_hi) = self.bands.mid;
+        let (treble_lo, treble_hi) = self.bands.treble;
+        audio_map.insert(AudioType::Bass, spectrum.band_level(bass_lo, bass_hi));
+        audio_map.insert(AudioType::Mid, spectrum.band_level(mid_lo, mid_hi));
+        audio_map.insert(AudioType::Treble, spectrum.band_level(treble_lo, treble_hi));
+
+        self.timbre.update(&spectrum, audio_map);
+
+        spectrum
     }
 }
 
 
+// The power of two number of samples closest to FFT_SECONDS
+fn fft_size(sample_rate : f64) -> usize {
+    let target = sample_rate * FFT_SECONDS;
+    let upper = (target.ceil() as usize).max(2).next_power_of_two();
+    let lower = upper / 2;
+    if target - lower as f64 <= upper as f64 - target {
+        lower.max(2)
+    }
+    else {
+        upper
+    }
+}
+
+// Points in the song are Durations for the clock but seconds for the maths
+pub fn seconds(time : Duration) -> f64 {
+    time.as_secs() as f64 + time.subsec_nanos() as f64 / 1e9
+}
+
+// Anything before the start of the song is the start
+pub fn from_seconds(secs : f64) -> Duration {
+    let secs = secs.max(0.0);
+    Duration::new(secs as u64, (secs.fract() * 1e9) as u32)
+}
+
 fn try_send_update(tx : &Sender<AudioPacket>, update : AudioPacket) {
 
     match tx.send(update) {
@@ -159,49 +774,38 @@ fn try_send_update(tx : &Sender<AudioPacket>, update : AudioPacket) {
 }
 
 // A "Time Window" focused on a particular point in an audio file
+// The samples are kept in a ring, oldest first starting at start
 #[derive(Debug)]
 struct TimeWindow<S : Sample> {
     size : usize,
 
-    current_sample : i64,
-
-    // The sum of all the samples the window
-    // can view
-    sum      : i64,
+    samples : Vec<S>,
+    start : usize,
 
-    // The sum of the difference of all the samples
-    // from the mean
-    dev_total : f64,
+    // The sum and sum of squares of all the samples the window can view
+    sum    : f64,
+    sum_sq : f64,
 
-    past     : LinkedList<S>,
-    present  : S,
-    future   : LinkedList<S>,
+    // Adding and taking away samples lets rounding errors creep into the
+    // sums, so they are worked out again from scratch every time round
+    steps_since_recount : usize,
 }
 
-impl TimeWindow<i32> {
+impl TimeWindow<f32> {
 
     fn new (size : usize) -> Self {
         if size == 0 {
             panic!("Tried to create a TimeWindow containing only the present!");
         }
-        let mut past = LinkedList::new();
-        let mut future = LinkedList::new();
-        // Fill with zeros initially
-        for _ in 0..size {
-            past.push_back(0);
-            future.push_back(0);
-        }
 
+        // Fill with zeros initially
         TimeWindow {
             size : size,
-            // We initialize the current sample to minus the size of the future list
-            // so when the future list is fully populated we will be at zero
-            current_sample : - (size as i64),
-            sum : 0,
-            past : past,
-            present : 0,
-            future : future,
-            dev_total : 0.0,
+            samples : vec![0.0; size * 2 + 1],
+            start : 0,
+            sum : 0.0,
+            sum_sq : 0.0,
+            steps_since_recount : 0,
         }
     }
 
@@ -210,75 +814,191 @@ impl TimeWindow<i32> {
         self.size * 2 + 1
     }
 
+    // The ith sample of the window, oldest first
+    fn get(&self, i : usize) -> f32 {
+        self.samples[(self.start + i) % self.total_size()]
+    }
+
+    fn present(&self) -> f32 {
+        self.get(self.size)
+    }
+
     fn avg(&self) -> f64 {
-        self.sum as f64 / self.total_size() as f64
+        self.sum / self.total_size() as f64
+    }
+
+    fn variance(&self) -> f64 {
+        let n = self.total_size() as f64;
+        let mean = self.sum / n;
+        // Can come out a hair below zero for a constant signal
+        (self.sum_sq / n - mean * mean).max(0.0)
     }
 
     fn std_dev(&self) -> f64 {
-        self.dev_total as f64 / self.total_size() as f64
+        self.variance().sqrt()
+    }
+
+    // Up to n samples centred on the present, oldest first
+    fn around_present(&self, n : usize) -> Vec<f32> {
+        let n_past = (n / 2).min(self.size);
+        let n_future = (n - n_past - 1).min(self.size);
+
+        (self.size - n_past .. self.size + n_future + 1).map(|i| self.get(i)).collect()
     }
 
     fn current_significant(&self) -> bool {
-        self.significant(self.present)
+        self.significant(self.present())
     }
 
-    fn significant(&self, s : i32) -> bool {
+    fn significant(&self, s : f32) -> bool {
         // Pretty arbitrary
         let diff =  (s as f64 - self.avg()).abs();
         diff > self.std_dev() * 4.0
     }
 
-    // Add a sample to the window, maybe pushing something
-    // out the other end
-    fn step_forwards(&mut self, s : i32) {
+    // Add a sample to the most futuristic part of the window, pushing the
+    // oldest sample of the past out the other end
+    fn step_forwards(&mut self, s : f32) {
+        let oldest = self.samples[self.start] as f64;
+        self.samples[self.start] = s;
+        self.start = (self.start + 1) % self.total_size();
 
-        self.current_sample += 1;
+        self.steps_since_recount += 1;
+        if self.steps_since_recount == self.total_size() {
+            self.recount();
+        }
+        else {
+            let s = s as f64;
+            self.sum += s - oldest;
+            self.sum_sq += s * s - oldest * oldest;
+        }
+    }
 
-        // Push into the most futuristic part of the future
-        self.future.push_back(s);
+    fn recount(&mut self) {
+        self.sum = self.samples.iter().fold(0.0, |acc, &s| acc + s as f64);
+        self.sum_sq = self.samples.iter().fold(0.0, |acc, &s| acc + (s as f64) * (s as f64));
+        self.steps_since_recount = 0;
+    }
+}
 
-        // Move a oldist future sample into the present
-        // Future should always be nonempty
-        let popped_future = self.future.pop_front().unwrap();
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+    use common::AudioSettings;
+    use clock::{Clock, ManualClock};
+    use super::{TimeWindow, PlayingSong, Transport, play_analysis};
+    use super::analysis::Analysis;
+    use super::synth::{SynthSong, Signal};
+
+    fn close(a : f64, b : f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
 
-        // Push the current onto the past 
-        // then replace present
-        self.past.push_back(self.present);
-        self.present = popped_future;
+    #[test]
+    fn empty_window() {
+        let tw = TimeWindow::new(2);
+        assert_eq!(tw.total_size(), 5);
+        assert!(close(tw.avg(), 0.0));
+        assert!(close(tw.std_dev(), 0.0));
+    }
 
-        // Pop off oldest sample, Past will always be non-empty
-        let popped_back = self.past.pop_front().unwrap();
+    #[test]
+    fn half_full() {
+        // The rest of the window is still zeros
+        let mut tw = TimeWindow::new(2);
+        tw.step_forwards(1.0);
+        tw.step_forwards(2.0);
+        assert_eq!(tw.present(), 0.0);
+        assert!(close(tw.avg(), 0.6));
+        assert!(close(tw.variance(), 0.64));
+    }
 
-        // Update the running sum of the samples
-        let new_sum = self.sum + (s as i64) - (popped_back as i64);
+    #[test]
+    fn full() {
+        let mut tw = TimeWindow::new(2);
+        for &s in [1.0, 2.0, 3.0, 4.0, 5.0].iter() {
+            tw.step_forwards(s);
+        }
+        assert_eq!(tw.present(), 3.0);
+        assert_eq!(tw.around_present(3), vec![2.0, 3.0, 4.0]);
+        assert_eq!(tw.around_present(100), vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert!(close(tw.avg(), 3.0));
+        assert!(close(tw.variance(), 2.0));
+    }
 
-        // Calculate deviation of old sample from old mean and new
-        // sample from new mean then update total
-        let old_dev = ((self.sum as f64) / (self.total_size() as f64) - (popped_back as f64)).abs();
-        let dev = ((new_sum as f64) / (self.total_size() as f64) - (s as f64)).abs();
-        self.dev_total = self.dev_total - old_dev + dev;
+    #[test]
+    fn sliding() {
+        // Moves along to 4..8, the same spread as 1..5
+        let mut tw = TimeWindow::new(2);
+        for &s in [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0].iter() {
+            tw.step_forwards(s);
+        }
+        assert_eq!(tw.present(), 6.0);
+        assert!(close(tw.avg(), 6.0));
+        assert!(close(tw.std_dev(), 2.0f64.sqrt()));
 
-        self.sum = new_sum;
+        assert!(!tw.significant(8.0));
+        assert!(tw.significant(20.0));
     }
-}
 
-#[allow(dead_code)]
-fn test() {
-    let mut tw = TimeWindow::new(2);
-    tw.step_forwards(1);
-    tw.step_forwards(2);
-    println!("{:?}", &tw);
-    tw.step_forwards(3);
-    tw.step_forwards(4);
-    println!("{:?}", &tw);
-    tw.step_forwards(5);
-    tw.step_forwards(6);
-    println!("{:?}", &tw);
-    tw.step_forwards(7);
-    tw.step_forwards(8);
-    println!("{:?}", &tw);
-    tw.step_forwards(1);
-    tw.step_forwards(2);
-    println!("{:?}", &tw);
-    println!("Is 8 sig? {}", tw.significant(8));
+    #[test]
+    fn constant_signal() {
+        // No spread however long it runs
+        let mut tw = TimeWindow::new(3);
+        for _ in 0 .. 1000 {
+            tw.step_forwards(0.5);
+        }
+        assert!(close(tw.avg(), 0.5));
+        assert!(tw.std_dev() < 1e-6);
+    }
+
+    #[test]
+    fn residue_after_silence() {
+        // A long loud stretch followed by silence shouldn't leave anything behind
+        let mut tw = TimeWindow::new(4);
+        for i in 0 .. 100_000 {
+            tw.step_forwards(if i % 2 == 0 { 0.9 } else { -0.7 });
+        }
+        for _ in 0 .. 9 {
+            tw.step_forwards(0.0);
+        }
+        assert!(tw.sum.abs() < 1e-9);
+        assert!(tw.std_dev() < 1e-6);
+    }
+
+    #[test]
+    fn song_ends_with_the_audio() {
+        // 8000 samples with 73 to an update, so the last frame runs past
+        // the end of the audio, where the clock stops like playback does
+        let mut song = SynthSong::new(Signal::WhiteNoise, 1.0, 0.5, 8000, 1);
+        let analysis = Analysis::analyse(&mut song, 0.05);
+        let clock = Arc::new(ManualClock::new(Duration::from_millis(10)));
+
+        let (done_tx, done_rx) = channel();
+        let player_clock = clock.clone();
+        thread::spawn(move || {
+            let (tx, _rx) = channel();
+            let (_transport_tx, transport_rx) = channel();
+            let mut transport = Transport::new(transport_rx, player_clock.clone());
+            let song = PlayingSong {
+                analysis : &analysis,
+                midi : None,
+                track : 0,
+                index : 0,
+                songs : 1,
+                start : Duration::new(0, 0),
+            };
+            play_analysis(&song, &mut AudioSettings::default(), &tx, &mut transport, &*player_clock);
+            let _ = done_tx.send(());
+        });
+
+        for _ in 0 .. 100 {
+            clock.tick();
+        }
+        assert_eq!(clock.position(), Duration::from_secs(1));
+        assert!(done_rx.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
 }
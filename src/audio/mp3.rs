@@ -1,28 +1,31 @@
 extern crate simplemad;
 use std::io::Read;
-use std::time::{Duration, SystemTime};
+use std::time::Duration;
 use std::iter::FlatMap;
 use std::rc::Rc;
-use self::simplemad::{Decoder, Frame, MadFixed32};
+use self::simplemad::{Decoder, Frame, MadFixed32, SimplemadError};
+use std::path::{Path, PathBuf};
 use audio::{Song, AudioData};
-use std::u32::MAX as U32MAX;
+use audio::playback::DecodedAudio;
 
 pub struct Mp3Song<T : Read> {
-    start_time : SystemTime,
     decoder : Decoder<T>,
     current_frame : Option<Frame>,
     current_frame_sample : usize,
     current_time : Duration,
+    sample_rate : u32,
+    name : PathBuf,
 }
 
 impl<T : Read> Song for Mp3Song<T> {
-    fn sample_max_value(&self) -> u32 {
-        U32MAX / 8
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
     }
-    //sample_max_value : u32 = U32MAX / 8;
 
-    fn play(&self) {
-        // TODO
+    // rodio can't decode mp3s, so we decode our own copy of the file and
+    // hand it the samples
+    fn decode(&self) -> Option<DecodedAudio> {
+        DecodedAudio::from_file(&self.name, |file| Mp3Song::new(file, &self.name))
     }
 }
 
@@ -41,11 +44,13 @@ impl<T : Read> Mp3Song<T> {
 
     }
 
-    // Get the current sample from the current frame
-    fn get_sample_from_frame(&self) -> Option<MadFixed32> {
+    // Get the current sample of every channel from the current frame
+    fn get_samples_from_frame(&self) -> Option<Vec<MadFixed32>> {
         self.current_frame.as_ref().and_then(|frame| {
             if self.current_frame_sample < frame.samples[0].len() {
-                Some(frame.samples[0][self.current_frame_sample])
+                Some(frame.samples.iter()
+                                  .map(|channel| channel[self.current_frame_sample])
+                                  .collect())
             }
             else {
                 None
@@ -63,7 +68,7 @@ impl<T : Read> Mp3Song<T> {
 impl<T : Read> Iterator for Mp3Song<T> {
     type Item = AudioData;
     fn next(&mut self) -> Option<Self::Item> {
-        let sample = self.get_sample_from_frame();
+        let sample = self.get_samples_from_frame();
 
         let sample2 = match sample {
             Some(x) => {
@@ -77,7 +82,7 @@ impl<T : Read> Iterator for Mp3Song<T> {
                 if (self.next_frame()) {
                     // If we just got an empty frame we end the stream
                     // but maybe we should try the next one?
-                    let x = self.get_sample_from_frame();
+                    let x = self.get_samples_from_frame();
                     if (x.is_some()) {
                         self.next_sample();
                     }
@@ -95,7 +100,7 @@ impl<T : Read> Iterator for Mp3Song<T> {
         sample2.map(|s| {
             // Otherwise construct an AudioData packet
             AudioData {
-                sample : s.to_i32(),
+                samples : s.iter().map(|x| x.to_f32()).collect(),
                 time : self.current_time,
             }
         })
@@ -104,8 +109,8 @@ impl<T : Read> Iterator for Mp3Song<T> {
 
 
 impl<T : Read> Mp3Song<T> {
-    pub fn new(t : T, start_time : SystemTime) -> Self {
-        let mut decoder = Decoder::decode(t).unwrap();
+    pub fn new(t : T, path : &Path) -> Result<Self, SimplemadError> {
+        let mut decoder = Decoder::decode(t)?;
         // Skip over first few
         let mut mframe = None;
         for frame in &mut decoder {
@@ -119,12 +124,16 @@ impl<T : Read> Mp3Song<T> {
                 }
             }
         }
-        Mp3Song {
+        // Take the sample rate from the first frame, mp3s can technically
+        // change it between frames but we ignore that
+        let sample_rate = mframe.as_ref().map(|f| f.sample_rate).unwrap_or(44100);
+        Ok(Mp3Song {
             decoder: decoder, 
-            start_time : start_time,
             current_frame : mframe,
             current_frame_sample : 0,
             current_time : Duration::new(0, 0),
-        }
+            sample_rate : sample_rate,
+            name : path.to_path_buf(),
+        })
     }
 }
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use common::AudioType;
+
+// With loudness normalisation a song's Level averages around this
+const TARGET_LEVEL : f64 = 0.4;
+
+// Roughly the Level of audio with a loudness of 0 LUFS before normalising
+const FULL_SCALE_LEVEL : f64 = 1.5;
+
+// Stops near silence being blown up to full Level when normalising by the peak
+const MIN_PEAK : f64 = 0.01;
+
+// How long a running peak takes to fall by half, so it can recover from
+// a single loud moment
+const PEAK_HALF_LIFE : f64 = 10.0;
+
+// The components that get scaled, they all measure the same thing
+const LEVELS : [AudioType; 3] = [AudioType::Level, AudioType::LevelLeft, AudioType::LevelRight];
+
+// How the Level components are scaled, chosen in the script
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normalisation {
+    // Level as it comes, quiet songs stay quiet
+    Off,
+    // Level relative to the loudest point of the song
+    Peak,
+    // Level scaled so every song has the same overall loudness
+    Loudness,
+}
+
+impl Default for Normalisation {
+    fn default() -> Self {
+        Normalisation::Loudness
+    }
+}
+
+// The gain stage applied to the components after analysis
+pub struct Normaliser {
+    mode : Normalisation,
+    peak : f64,
+    // Multiplied into the peak every update, 1.0 if the peak is for the whole song
+    peak_decay : f64,
+    // In LUFS
+    loudness : Option<f64>,
+}
+
+impl Normaliser {
+    // For a song analysed ahead of time, when we know what it's like all the way through
+    pub fn for_song(mode : Normalisation, peak : f64, loudness : Option<f64>) -> Self {
+        Normaliser {
+            mode : mode,
+            peak : peak,
+            peak_decay : 1.0,
+            loudness : loudness,
+        }
+    }
+
+    // For audio analysed as it arrives, the measurements are kept up to
+    // date with observe
+    pub fn running(mode : Normalisation, update_rate : f64) -> Self {
+        Normaliser {
+            mode : mode,
+            peak : 0.0,
+            peak_decay : 0.5f64.powf(1.0 / (PEAK_HALF_LIFE * update_rate)),
+            loudness : None,
+        }
+    }
+
+    pub fn observe(&mut self, components : &HashMap<AudioType, f64>, loudness : Option<f64>) {
+        let level = components.get(&AudioType::Level).cloned().unwrap_or(0.0);
+        self.peak = (self.peak * self.peak_decay).max(level);
+        self.loudness = loudness;
+    }
+
+    pub fn apply(&self, components : &mut HashMap<AudioType, f64>) {
+        let gain = match self.mode {
+            Normalisation::Off => return,
+            Normalisation::Peak => 1.0 / self.peak.max(MIN_PEAK),
+            Normalisation::Loudness => match self.loudness {
+                Some(l) => TARGET_LEVEL / (FULL_SCALE_LEVEL * 10.0f64.powf(l / 20.0)),
+                // Nothing but silence so far
+                None => 1.0,
+            },
+        };
+
+        for t in LEVELS.iter() {
+            if let Some(x) = components.get_mut(t) {
+                *x = (*x * gain).min(1.0);
+            }
+        }
+    }
+}
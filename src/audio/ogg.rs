@@ -0,0 +1,99 @@
+extern crate lewton;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use self::lewton::VorbisError;
+use self::lewton::header::HeaderReadError;
+use self::lewton::inside_ogg::OggStreamReader;
+use audio::{Song, AudioData, from_seconds};
+use audio::playback::DecodedAudio;
+
+pub struct OggSong<T : Read + Seek> {
+    reader : OggStreamReader<T>,
+    // Interleaved samples of the last decoded packet
+    current_packet : Vec<i16>,
+    current_packet_sample : usize,
+    current_sample : usize,
+    channels : usize,
+    sample_rate : u32,
+    name : PathBuf,
+}
+
+impl<T : Read + Seek> OggSong<T> {
+    pub fn new(t : T, path : &Path) -> Result<Self, VorbisError> {
+        let reader = OggStreamReader::new(t)?;
+        let channels = reader.ident_hdr.audio_channels as usize;
+        let sample_rate = reader.ident_hdr.audio_sample_rate;
+        // Every frame is read a channel at a time, so there has to be one
+        if channels == 0 || sample_rate == 0 {
+            return Err(VorbisError::BadHeader(HeaderReadError::HeaderBadFormat));
+        }
+        Ok(OggSong {
+            reader : reader,
+            current_packet : Vec::new(),
+            current_packet_sample : 0,
+            current_sample : 0,
+            channels : channels,
+            sample_rate : sample_rate,
+            name : path.to_path_buf(),
+        })
+    }
+
+    // Decode the next packet
+    // Returns whether it was successful
+    fn next_packet(&mut self) -> bool {
+        match self.reader.read_dec_packet_itl() {
+            Ok(Some(packet)) => {
+                self.current_packet = packet;
+                self.current_packet_sample = 0;
+                true
+            }
+            Ok(None) => false,
+            Err(e) => {
+                println!("Error decoding ogg: {:?}", e);
+                false
+            }
+        }
+    }
+
+    fn packet_finished(&self) -> bool {
+        (self.current_packet_sample + 1) * self.channels > self.current_packet.len()
+    }
+}
+
+impl<T : Read + Seek> Song for OggSong<T> {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn decode(&self) -> Option<DecodedAudio> {
+        DecodedAudio::from_file(&self.name, |file| OggSong::new(file, &self.name))
+    }
+}
+
+impl<T : Read + Seek> Iterator for OggSong<T> {
+    type Item = AudioData;
+    fn next(&mut self) -> Option<Self::Item> {
+        // The first packet of a stream decodes to nothing, so keep going
+        // until we find samples
+        while self.packet_finished() {
+            if !self.next_packet() {
+                return None;
+            }
+        }
+
+        let start = self.current_packet_sample * self.channels;
+        let samples = self.current_packet[start .. start + self.channels]
+                          .iter()
+                          .map(|&s| s as f32 / 32768.0)
+                          .collect::<Vec<f32>>();
+
+        let t = self.current_sample as f64 / self.sample_rate as f64;
+        let dur = from_seconds(t);
+        self.current_packet_sample += 1;
+        self.current_sample += 1;
+        Some(AudioData {
+            time : dur,
+            samples : samples,
+        })
+    }
+}
@@ -0,0 +1,216 @@
+use std::io::{self, Read, Seek, SeekFrom, BufReader};
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::time::Duration;
+use audio::{Song, AudioData, seconds, from_seconds};
+use audio::playback::DecodedAudio;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PcmFormat {
+    U8,
+    S16Le,
+    S16Be,
+    S24Le,
+    S32Le,
+    F32Le,
+}
+
+impl PcmFormat {
+    fn from_name(name : &str) -> Option<Self> {
+        match name.to_lowercase().as_ref() {
+            "u8" => Some(PcmFormat::U8),
+            "s16" | "s16le" => Some(PcmFormat::S16Le),
+            "s16be" => Some(PcmFormat::S16Be),
+            "s24" | "s24le" => Some(PcmFormat::S24Le),
+            "s32" | "s32le" => Some(PcmFormat::S32Le),
+            "f32" | "f32le" => Some(PcmFormat::F32Le),
+            _ => None,
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        match *self {
+            PcmFormat::U8 => 1,
+            PcmFormat::S16Le | PcmFormat::S16Be => 2,
+            PcmFormat::S24Le => 3,
+            PcmFormat::S32Le | PcmFormat::F32Le => 4,
+        }
+    }
+
+    // Convert the bytes of a single sample to -1..1
+    fn decode(&self, b : &[u8]) -> f32 {
+        match *self {
+            PcmFormat::U8 => (b[0] as f32 - 128.0) / 128.0,
+            PcmFormat::S16Le => (((b[1] as i16) << 8) | b[0] as i16) as f32 / 32768.0,
+            PcmFormat::S16Be => (((b[0] as i16) << 8) | b[1] as i16) as f32 / 32768.0,
+            PcmFormat::S24Le => {
+                // Shift up to the top of an i32 so the sign comes along
+                let x = ((b[2] as i32) << 24) | ((b[1] as i32) << 16) | ((b[0] as i32) << 8);
+                (x >> 8) as f32 / 8388608.0
+            }
+            PcmFormat::S32Le => {
+                let x = ((b[3] as i32) << 24) | ((b[2] as i32) << 16) | ((b[1] as i32) << 8) | b[0] as i32;
+                x as f32 / 2147483648.0
+            }
+            PcmFormat::F32Le => {
+                let bits = ((b[3] as u32) << 24) | ((b[2] as u32) << 16) | ((b[1] as u32) << 8) | b[0] as u32;
+                f32::from_bits(bits)
+            }
+        }
+    }
+}
+
+// Raw interleaved PCM arriving live from stdin or a named pipe, for example
+// from another process on the same machine that is playing it.
+// Since it's being heard as it arrives we don't play it or sleep on it.
+pub struct PcmSong {
+    reader : BufReader<Box<Read>>,
+    format : PcmFormat,
+    channels : usize,
+    sample_rate : u32,
+    current_sample : usize,
+    buf : Vec<u8>,
+    // Set if the source is a plain file, which unlike a pipe can be seeked
+    file : Option<PathBuf>,
+}
+
+impl PcmSong {
+    // Takes a spec of the form "source,rate=44100,channels=2,format=s16le"
+    // where the source is "-" for stdin or the path of a file or pipe.
+    // The options can be in any order and default to the values above.
+    pub fn from_spec(spec : &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let source = parts.next().unwrap_or("-");
+
+        let mut format = PcmFormat::S16Le;
+        let mut channels = 2;
+        let mut sample_rate = 44100;
+
+        for part in parts {
+            let mut kv = part.splitn(2, '=');
+            let key = kv.next().unwrap_or("").trim().to_lowercase();
+            let value = kv.next().unwrap_or("").trim();
+            match key.as_ref() {
+                "rate" => {
+                    sample_rate = value.parse().map_err(|_| format!("Invalid sample rate: {}", value))?;
+                }
+                "channels" => {
+                    channels = value.parse().map_err(|_| format!("Invalid channel count: {}", value))?;
+                }
+                "format" => {
+                    format = PcmFormat::from_name(value).ok_or(format!("Unknown pcm format: {}", value))?;
+                }
+                x => return Err(format!("Unknown pcm option: {}", x)),
+            }
+        }
+
+        if channels == 0 || sample_rate == 0 {
+            return Err(format!("Need at least one channel and a non zero sample rate"));
+        }
+
+        let is_file = source != "-" && fs::metadata(source).map(|m| m.is_file()).unwrap_or(false);
+        let reader : Box<Read> = if source == "-" {
+            Box::new(io::stdin())
+        }
+        else {
+            // Opening a pipe blocks until something starts writing to it
+            Box::new(File::open(source).map_err(|e| format!("Couldn't open {}: {}", source, e))?)
+        };
+
+        Ok(PcmSong {
+            reader : BufReader::new(reader),
+            format : format,
+            channels : channels,
+            sample_rate : sample_rate,
+            current_sample : 0,
+            buf : vec![0; format.bytes() * channels],
+            file : if is_file { Some(PathBuf::from(source)) } else { None },
+        })
+    }
+}
+
+impl Song for PcmSong {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn decode(&self) -> Option<DecodedAudio> {
+        // Whatever is sending us audio is already playing it
+        None
+    }
+
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    fn seek(&mut self, time : Duration) -> Result<(), String> {
+        let path = match self.file {
+            Some(ref x) => x.clone(),
+            None => return Err("Can't seek in audio arriving from a pipe".to_string()),
+        };
+
+        // Stop at the end rather than past it
+        let frame_bytes = self.buf.len() as u64;
+        let mut file = File::open(&path).map_err(|e| format!("Couldn't open {}: {}", path.display(), e))?;
+        let frames = file.metadata().map_err(|e| e.to_string())?.len() / frame_bytes;
+        let frame = ((seconds(time) * self.sample_rate as f64) as u64).min(frames);
+        file.seek(SeekFrom::Start(frame * frame_bytes)).map_err(|e| e.to_string())?;
+
+        self.reader = BufReader::new(Box::new(file));
+        self.current_sample = frame as usize;
+        Ok(())
+    }
+}
+
+impl Iterator for PcmSong {
+    type Item = AudioData;
+    fn next(&mut self) -> Option<Self::Item> {
+        // Ends when the other end closes the stream
+        if self.reader.read_exact(&mut self.buf).is_err() {
+            return None;
+        }
+
+        let bytes = self.format.bytes();
+        let samples = self.buf.chunks(bytes)
+                              .map(|b| self.format.decode(b))
+                              .collect::<Vec<f32>>();
+
+        let t = self.current_sample as f64 / self.sample_rate as f64;
+        let dur = from_seconds(t);
+        self.current_sample += 1;
+        Some(AudioData {
+            time : dur,
+            samples : samples,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::time::Duration;
+    use audio::Song;
+    use super::PcmSong;
+
+    #[test]
+    fn seek_in_file() {
+        // A second of mono u8 at 100 Hz, each sample counting up from silence
+        let path = env::temp_dir().join("audisuals-pcm-seek.raw");
+        let bytes = (0 .. 100).map(|i| 128 + i as u8).collect::<Vec<u8>>();
+        File::create(&path).and_then(|mut f| f.write_all(&bytes)).unwrap();
+
+        let mut song = PcmSong::from_spec(&format!("{},rate=100,channels=1,format=u8", path.display())).unwrap();
+        song.next();
+        song.seek(Duration::from_millis(500)).unwrap();
+        let data = song.next().unwrap();
+        assert_eq!(data.samples, vec![50.0 / 128.0]);
+        assert_eq!(data.time, Duration::from_millis(500));
+
+        // Past the end is the end
+        song.seek(Duration::from_secs(5)).unwrap();
+        assert!(song.next().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
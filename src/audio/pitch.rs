@@ -0,0 +1,154 @@
+use audio::fft::{Fft, Complex};
+use audio::spectrum::Spectrum;
+
+// Range of pitches we look for
+const MIN_PITCH_HZ : f64 = 50.0;
+const MAX_PITCH_HZ : f64 = 2000.0;
+
+// How aperiodic the audio can be and still have a pitch, lower is stricter
+const YIN_THRESHOLD : f64 = 0.15;
+
+// Below this mean square the audio is too quiet to have a pitch
+const SILENCE : f64 = 1e-7;
+
+// Chroma is taken from this range, below it the FFT bins are too wide to
+// tell notes apart
+const CHROMA_LO_HZ : f64 = 100.0;
+const CHROMA_HI_HZ : f64 = 5000.0;
+
+// Finds the fundamental frequency of a block of samples with the YIN
+// algorithm, using an FFT to do the autocorrelation
+pub struct PitchTracker {
+    sample_rate : f64,
+    // Samples looked at each time, the first half is compared against
+    // itself shifted by up to another half
+    size : usize,
+    fft : Fft,
+    signal : Vec<Complex>,
+    head : Vec<Complex>,
+}
+
+impl PitchTracker {
+    pub fn new(sample_rate : f64) -> Self {
+        // Long enough for the comparison to cover the lowest pitch
+        let size = ((2.0 * sample_rate / MIN_PITCH_HZ).ceil() as usize).next_power_of_two();
+        PitchTracker {
+            sample_rate : sample_rate,
+            size : size,
+            fft : Fft::new(size * 2),
+            signal : vec![Complex::new(0.0, 0.0); size * 2],
+            head : vec![Complex::new(0.0, 0.0); size * 2],
+        }
+    }
+
+    // How many samples detect wants
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // The pitch in Hz, None if there isn't a clear one
+    pub fn detect(&mut self, samples : &[f64]) -> Option<f64> {
+        let n = self.size;
+        let w = n / 2;
+        let x = |i : usize| samples.get(i).cloned().unwrap_or(0.0);
+
+        let energy = (0 .. n).fold(0.0, |acc, i| acc + x(i) * x(i));
+        if energy / (n as f64) < SILENCE {
+            return None;
+        }
+
+        let r = self.correlate(&x);
+
+        // Squared difference between the first half and the samples tau on,
+        // worked out from the correlation and running sums of squares
+        let head_energy = (0 .. w).fold(0.0, |acc, j| acc + x(j) * x(j));
+        let mut shifted_energy = head_energy;
+        let mut diff = Vec::with_capacity(w);
+        for tau in 0 .. w {
+            diff.push((head_energy + shifted_energy - 2.0 * r[tau]).max(0.0));
+            shifted_energy += x(tau + w) * x(tau + w) - x(tau) * x(tau);
+        }
+
+        // Normalise each difference by the average of those before it
+        let mut norm = vec![1.0; w];
+        let mut running = 0.0;
+        for tau in 1 .. w {
+            running += diff[tau];
+            norm[tau] = if running > 0.0 { diff[tau] * tau as f64 / running } else { 1.0 };
+        }
+
+        let tau_min = ((self.sample_rate / MAX_PITCH_HZ) as usize).max(2);
+        let tau_max = ((self.sample_rate / MIN_PITCH_HZ) as usize).min(w - 2);
+
+        // The first dip under the threshold, followed down to its bottom
+        let mut tau = tau_min;
+        while tau < tau_max && norm[tau] >= YIN_THRESHOLD {
+            tau += 1;
+        }
+        if tau >= tau_max {
+            return None;
+        }
+        while tau + 1 < tau_max && norm[tau + 1] < norm[tau] {
+            tau += 1;
+        }
+
+        // Fit a parabola through the bottom for a period between samples
+        let (a, b, c) = (norm[tau - 1], norm[tau], norm[tau + 1]);
+        let denom = a - 2.0 * b + c;
+        let offset = if denom.abs() > 1e-12 { 0.5 * (a - c) / denom } else { 0.0 };
+
+        Some(self.sample_rate / (tau as f64 + offset))
+    }
+
+    // Correlation of the first half of the samples with all of them at each lag
+    fn correlate<F : Fn(usize) -> f64>(&mut self, x : &F) -> Vec<f64> {
+        let n = self.size;
+        for i in 0 .. n * 2 {
+            self.signal[i] = Complex::new(if i < n { x(i) } else { 0.0 }, 0.0);
+            self.head[i] = Complex::new(if i < n / 2 { x(i) } else { 0.0 }, 0.0);
+        }
+
+        self.fft.process(&mut self.signal);
+        self.fft.process(&mut self.head);
+
+        // Multiply by the conjugate, conjugated again so the forward
+        // transform does the inverse
+        for (s, h) in self.signal.iter_mut().zip(self.head.iter()) {
+            let re = s.re * h.re + s.im * h.im;
+            let im = s.im * h.re - s.re * h.im;
+            *s = Complex::new(re, -im);
+        }
+        self.fft.process(&mut self.signal);
+
+        let scale = 1.0 / (n * 2) as f64;
+        self.signal[.. n / 2].iter().map(|c| c.re * scale).collect()
+    }
+}
+
+// Position of a pitch within the octave, 0 for C up to 11/12 for B
+pub fn note_of(hz : f64) -> f64 {
+    let midi = 69.0 + 12.0 * (hz / 440.0).log2();
+    let class = ((midi.round() as i64 % 12) + 12) % 12;
+    class as f64 / 12.0
+}
+
+// Energy of each of the 12 pitch classes starting at C, scaled so the
+// strongest is 1
+pub fn chroma(spectrum : &Spectrum) -> [f64; 12] {
+    let mut chroma = [0.0; 12];
+    for (a, &c) in spectrum.bins.iter().zip(spectrum.centres.iter()) {
+        if c < CHROMA_LO_HZ || c >= CHROMA_HI_HZ {
+            continue;
+        }
+        let class = (note_of(c) * 12.0).round() as usize % 12;
+        chroma[class] += a * a;
+    }
+
+    let max = chroma.iter().fold(0.0, |acc : f64, &x| acc.max(x));
+    if max > 0.0 {
+        for x in chroma.iter_mut() {
+            *x /= max;
+        }
+    }
+    chroma
+}
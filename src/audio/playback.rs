@@ -0,0 +1,294 @@
+use std::sync::{Arc, Mutex};
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+use std::collections::VecDeque;
+use std::time::Duration;
+use audio::Song;
+use clock::Clock;
+use rodio;
+use rodio::Source;
+
+// A whole song decoded into memory, samples are interleaved
+pub struct DecodedAudio {
+    pub samples : Vec<f32>,
+    pub channels : u16,
+    pub sample_rate : u32,
+}
+
+impl DecodedAudio {
+    // Run a song to the end collecting all its samples
+    pub fn from_song<S : Song + ?Sized>(song : &mut S) -> Self {
+        let sample_rate = song.sample_rate();
+        let mut channels = 0;
+        let mut samples = Vec::new();
+        while let Some(data) = song.next() {
+            channels = data.samples.len();
+            samples.extend(data.samples);
+        }
+
+        DecodedAudio {
+            samples : samples,
+            channels : channels.max(1) as u16,
+            sample_rate : sample_rate,
+        }
+    }
+
+    // Decode a fresh copy of a song file with open, for songs that are read
+    // as they're analysed. The file could have gone or changed since then, so
+    // failing just means there's nothing to play.
+    pub fn from_file<S, E, F>(path : &Path, open : F) -> Option<Self>
+        where S : Song, E : Debug, F : FnOnce(BufReader<File>) -> Result<S, E>
+    {
+        let file = match File::open(path) {
+            Ok(x) => BufReader::new(x),
+            Err(e) => {
+                println!("Error: couldn't open {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        match open(file) {
+            Ok(mut song) => Some(DecodedAudio::from_song(&mut song)),
+            Err(e) => {
+                println!("Error: couldn't read {}: {:?}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    // The same audio with a different number of channels and sample rate.
+    // Channels are mixed down to mono if they don't match, and the rate is
+    // changed by interpolating between samples.
+    fn convert(self, channels : u16, sample_rate : u32) -> Self {
+        if self.channels == channels && self.sample_rate == sample_rate {
+            return self;
+        }
+
+        let from_channels = self.channels as usize;
+        let to_channels = channels as usize;
+        let frames = self.frames();
+        let frame = |i : usize| -> Vec<f32> {
+            let i = i.min(frames.saturating_sub(1));
+            let samples = &self.samples[i * from_channels .. (i + 1) * from_channels];
+            if from_channels == to_channels {
+                samples.to_vec()
+            }
+            else {
+                let mono = samples.iter().fold(0.0, |acc, x| acc + x) / from_channels as f32;
+                vec![mono; to_channels]
+            }
+        };
+
+        let ratio = self.sample_rate as f64 / sample_rate as f64;
+        let out_frames = (frames as f64 / ratio) as usize;
+        let mut samples = Vec::with_capacity(out_frames * to_channels);
+        for i in 0 .. out_frames {
+            let pos = i as f64 * ratio;
+            let (a, b) = (frame(pos as usize), frame(pos as usize + 1));
+            let t = pos.fract() as f32;
+            samples.extend(a.iter().zip(b.iter()).map(|(x, y)| x + (y - x) * t));
+        }
+
+        DecodedAudio {
+            samples : samples,
+            channels : channels,
+            sample_rate : sample_rate,
+        }
+    }
+}
+
+// State shared between the output stream and anything following it
+struct PlaybackState {
+    // Index of the next frame to be played
+    frame : AtomicUsize,
+    paused : AtomicBool,
+    // Frames queued so far, where the next song queued will start
+    end : AtomicUsize,
+    // Songs queued that the output hasn't picked up yet, with the frame
+    // each starts at
+    incoming : Mutex<Vec<(usize, DecodedAudio)>>,
+}
+
+// Handle on a song being played through the speakers, the clock follows
+// what the output stream has actually consumed. More songs can be queued
+// to follow on without a gap, the clock keeps counting up through all of them.
+#[derive(Clone)]
+pub struct Playback {
+    state : Arc<PlaybackState>,
+    channels : u16,
+    sample_rate : u32,
+}
+
+impl Playback {
+    // Play a song straight after everything queued so far, returns the
+    // point on the clock it starts at
+    pub fn queue(&self, audio : DecodedAudio) -> Duration {
+        let audio = audio.convert(self.channels, self.sample_rate);
+        let start = self.state.end.fetch_add(audio.frames(), Ordering::SeqCst);
+        self.state.incoming.lock().unwrap().push((start, audio));
+        self.time_of(start)
+    }
+
+    fn time_of(&self, frame : usize) -> Duration {
+        let frame = frame as u64;
+        let rate = self.sample_rate as u64;
+        let nanos = (frame % rate) * 1_000_000_000 / rate;
+        Duration::new(frame / rate, nanos as u32)
+    }
+}
+
+impl Clock for Playback {
+    fn position(&self) -> Duration {
+        self.time_of(self.state.frame.load(Ordering::SeqCst))
+    }
+
+    fn set_paused(&self, paused : bool) {
+        self.state.paused.store(paused, Ordering::SeqCst);
+    }
+
+    fn seek(&self, time : Duration) {
+        let rate = self.sample_rate as u64;
+        let frame = time.as_secs() * rate + (time.subsec_nanos() as u64) * rate / 1_000_000_000;
+        self.state.frame.store(frame as usize, Ordering::SeqCst);
+    }
+}
+
+// Start playing the audio on the default output, returns None if there
+// is no output to play on
+pub fn play(audio : DecodedAudio) -> Option<Playback> {
+    let endpoint = match rodio::get_default_endpoint() {
+        Some(x) => x,
+        None => {
+            println!("No audio output found, playing silently");
+            return None;
+        }
+    };
+
+    let state = Arc::new(PlaybackState {
+        frame : AtomicUsize::new(0),
+        paused : AtomicBool::new(false),
+        end : AtomicUsize::new(audio.frames()),
+        incoming : Mutex::new(Vec::new()),
+    });
+
+    let playback = Playback {
+        state : state.clone(),
+        channels : audio.channels,
+        sample_rate : audio.sample_rate,
+    };
+
+    let mut songs = VecDeque::new();
+    let (channels, sample_rate) = (audio.channels, audio.sample_rate);
+    songs.push_back((0, audio));
+
+    let source = BufferSource {
+        songs : songs,
+        state : state,
+        channels : channels,
+        sample_rate : sample_rate,
+        frame : 0,
+        channel : 0,
+    };
+
+    rodio::play_raw(&endpoint, source);
+    Some(playback)
+}
+
+// Feeds decoded songs to rodio one after another, playing silence once
+// they are all over
+struct BufferSource {
+    // Songs we have picked up with the frame each starts at, finished ones
+    // are dropped as we go
+    songs : VecDeque<(usize, DecodedAudio)>,
+    state : Arc<PlaybackState>,
+    channels : u16,
+    sample_rate : u32,
+    // The frame and channel of the next sample
+    frame : usize,
+    channel : usize,
+}
+
+impl BufferSource {
+    // The song playing at a frame and how far into it the frame is
+    fn find(&mut self, frame : usize) -> Option<(usize, usize)> {
+        // Only the song at the front can be finished with
+        while self.songs.len() > 1 && frame >= self.songs[1].0 {
+            self.songs.pop_front();
+        }
+
+        let found = self.songs.iter()
+                              .position(|&(start, ref audio)| frame >= start && frame < start + audio.frames());
+        match found {
+            Some(i) => Some((i, frame - self.songs[i].0)),
+            None => {
+                // Don't hold up the output waiting for the lock, we'll be back
+                // for the next sample anyway
+                let mut picked_up = false;
+                if let Ok(mut incoming) = self.state.incoming.try_lock() {
+                    picked_up = !incoming.is_empty();
+                    self.songs.extend(incoming.drain(..));
+                }
+                if picked_up { self.find(frame) } else { None }
+            }
+        }
+    }
+}
+
+impl Iterator for BufferSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let channels = self.channels as usize;
+
+        // Only pick up a new position at the start of a frame, so the
+        // channels never get swapped around
+        if self.channel == 0 {
+            self.frame = self.state.frame.load(Ordering::SeqCst);
+        }
+
+        let frame = self.frame;
+        let found = if self.state.paused.load(Ordering::SeqCst) { None } else { self.find(frame) };
+        let (song, offset) = match found {
+            Some(x) => x,
+            None => {
+                self.channel = (self.channel + 1) % channels;
+                return Some(0.0);
+            }
+        };
+
+        let sample = self.songs[song].1.samples[offset * channels + self.channel];
+
+        self.channel += 1;
+        if self.channel == channels {
+            self.channel = 0;
+            // Leave the position alone if someone else moved it in the meantime
+            let _ = self.state.frame.compare_exchange(self.frame, self.frame + 1, Ordering::SeqCst, Ordering::SeqCst);
+        }
+
+        Some(sample)
+    }
+}
+
+impl Source for BufferSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn samples_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
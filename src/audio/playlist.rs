@@ -0,0 +1,85 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// Files with these extensions are picked up when playing a whole directory
+const AUDIO_EXTENSIONS : [&'static str; 5] = ["wav", "mp3", "flac", "ogg", "oga"];
+
+// The songs to play one after another
+pub struct Playlist {
+    paths : Vec<PathBuf>,
+}
+
+impl Playlist {
+    // An M3U playlist, every audio file in a directory in name order, or
+    // otherwise a single song
+    pub fn open(path : &Path) -> Result<Self, String> {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+
+        let paths = if path.is_dir() {
+            from_directory(path)?
+        }
+        else if ext == "m3u" || ext == "m3u8" {
+            from_m3u(path)?
+        }
+        else {
+            vec![path.to_path_buf()]
+        };
+
+        if paths.is_empty() {
+            return Err(format!("No songs found in {}", path.display()));
+        }
+
+        Ok(Playlist {
+            paths : paths,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn get(&self, i : usize) -> &Path {
+        &self.paths[i]
+    }
+}
+
+fn from_directory(dir : &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Couldn't read {}: {}", dir.display(), e))?;
+
+    let mut paths = entries.filter_map(|entry| entry.ok().map(|e| e.path()))
+                           .filter(|p| p.is_file())
+                           .filter(|p| {
+                               let ext = p.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+                               AUDIO_EXTENSIONS.contains(&ext.as_ref())
+                           })
+                           .collect::<Vec<PathBuf>>();
+    paths.sort();
+    Ok(paths)
+}
+
+// One song on each line, anything starting with # is a comment or extra
+// information we don't need. Paths are relative to the playlist.
+fn from_m3u(path : &Path) -> Result<Vec<PathBuf>, String> {
+    let mut bytes = Vec::new();
+    File::open(path).and_then(|mut f| f.read_to_end(&mut bytes))
+                    .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+    // Older playlists aren't always UTF-8
+    let text = String::from_utf8_lossy(&bytes);
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let paths = text.lines()
+                    .map(|line| line.trim().trim_left_matches('\u{feff}'))
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(|line| {
+                        // Generated audio isn't a file so stays as it is
+                        if line.starts_with("synth:") || line.starts_with("pcm:") {
+                            PathBuf::from(line)
+                        }
+                        else {
+                            dir.join(line)
+                        }
+                    })
+                    .collect();
+    Ok(paths)
+}
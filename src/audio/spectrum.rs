@@ -0,0 +1,182 @@
+use std::sync::Arc;
+use audio::fft::{Fft, Complex, hann_window};
+
+// Band energies are scaled by this to roughly match Level
+const BAND_GAIN : f64 = 2.5;
+
+// The equivalent noise bandwidth of the Hann window in bins, used to correct
+// for the energy of a single tone being smeared over neighbouring bins
+const HANN_ENBW : f64 = 1.5;
+
+// Amplitude spectrum of a short slice of audio
+#[derive(Clone, Debug)]
+pub struct Spectrum {
+    // Amplitude of each bin, scaled so a sine wave of amplitude A peaks at A
+    pub bins : Vec<f64>,
+    // Centre of each bin in Hz, shared between all spectra with the same bins
+    pub centres : Arc<Vec<f64>>,
+}
+
+impl Spectrum {
+    pub fn empty() -> Self {
+        Spectrum {
+            bins : Vec::new(),
+            centres : Arc::new(Vec::new()),
+        }
+    }
+
+    // Energy of the bins with centres in [lo_hz, hi_hz), given as the amplitude
+    // of a single sine wave carrying the same energy
+    pub fn band_energy(&self, lo_hz : f64, hi_hz : f64) -> f64 {
+        if hi_hz <= lo_hz {
+            return 0.0;
+        }
+
+        let total = self.bins.iter()
+                             .zip(self.centres.iter())
+                             .filter(|&(_, &c)| c >= lo_hz && c < hi_hz)
+                             .fold(0.0, |acc, (a, _)| acc + a * a);
+        (total / HANN_ENBW).sqrt()
+    }
+
+    // Band energy scaled for use as an audio component
+    pub fn band_level(&self, lo_hz : f64, hi_hz : f64) -> f64 {
+        BAND_GAIN * self.band_energy(lo_hz, hi_hz)
+    }
+}
+
+// Runs a Hann windowed FFT over blocks of samples
+pub struct SpectrumAnalyser {
+    fft : Fft,
+    window : Vec<f64>,
+    // Converts FFT magnitudes to sine amplitudes
+    scale : f64,
+    centres : Arc<Vec<f64>>,
+    buf : Vec<Complex>,
+}
+
+impl SpectrumAnalyser {
+    pub fn new(size : usize, sample_rate : f64) -> Self {
+        let window = hann_window(size);
+        let window_sum = window.iter().fold(0.0, |acc, w| acc + w);
+        let bin_width = sample_rate / (size as f64);
+        SpectrumAnalyser {
+            fft : Fft::new(size),
+            window : window,
+            scale : 2.0 / window_sum,
+            centres : Arc::new((0 .. size / 2).map(|k| k as f64 * bin_width).collect()),
+            buf : vec![Complex::new(0.0, 0.0); size],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.fft.size()
+    }
+
+    // Samples should be normalised, if fewer than the FFT size are given
+    // the rest of the block is zero padded
+    pub fn analyse(&mut self, samples : &[f64]) -> Spectrum {
+        for (i, c) in self.buf.iter_mut().enumerate() {
+            let s = samples.get(i).cloned().unwrap_or(0.0);
+            *c = Complex::new(s * self.window[i], 0.0);
+        }
+
+        self.fft.process(&mut self.buf);
+
+        // Only the first half is meaningful for a real input
+        let scale = self.scale;
+        let bins = self.buf[.. self.fft.size() / 2].iter()
+                                                   .map(|c| c.norm() * scale)
+                                                   .collect();
+        Spectrum {
+            bins : bins,
+            centres : self.centres.clone(),
+        }
+    }
+
+    pub fn centres(&self) -> &[f64] {
+        &self.centres
+    }
+}
+
+// Merges the evenly spaced bins of an FFT into bands spaced evenly in pitch,
+// so a spectrum can be kept with far fewer bins without losing the bass.
+// Bands that no bin falls in are left out, so there can be fewer than asked for.
+pub struct BinMerger {
+    // Range of source bins making up each band
+    groups : Vec<(usize, usize)>,
+    centres : Arc<Vec<f64>>,
+}
+
+impl BinMerger {
+    pub fn new(source_centres : &[f64], bands : usize, lo_hz : f64) -> Self {
+        let hi_hz = source_centres.last().cloned().unwrap_or(lo_hz).max(lo_hz);
+        let ratio = (hi_hz / lo_hz).ln() / bands as f64;
+
+        let mut groups : Vec<(usize, usize)> = Vec::new();
+        let mut last_band = None;
+        for (i, &c) in source_centres.iter().enumerate() {
+            if c < lo_hz {
+                continue;
+            }
+            let band = (((c / lo_hz).ln() / ratio) as usize).min(bands - 1);
+            if last_band == Some(band) {
+                groups.last_mut().unwrap().1 = i + 1;
+            }
+            else {
+                groups.push((i, i + 1));
+                last_band = Some(band);
+            }
+        }
+
+        let centres = groups.iter().map(|&(lo, hi)| {
+            source_centres[lo .. hi].iter().fold(0.0, |acc, c| acc + c) / (hi - lo) as f64
+        }).collect();
+
+        BinMerger {
+            groups : groups,
+            centres : Arc::new(centres),
+        }
+    }
+
+    pub fn centres(&self) -> Arc<Vec<f64>> {
+        self.centres.clone()
+    }
+
+    // Each band carries the combined energy of the bins it covers
+    pub fn merge(&self, spectrum : &Spectrum) -> Spectrum {
+        let bins = self.groups.iter().map(|&(lo, hi)| {
+            spectrum.bins[lo .. hi].iter().fold(0.0, |acc, a| acc + a * a).sqrt()
+        }).collect();
+
+        Spectrum {
+            bins : bins,
+            centres : self.centres.clone(),
+        }
+    }
+}
+
+// Frequency ranges in Hz used for the Bass, Mid and Treble components
+#[derive(Clone, Debug)]
+pub struct FrequencyBands {
+    pub bass : (f64, f64),
+    pub mid : (f64, f64),
+    pub treble : (f64, f64),
+}
+
+impl FrequencyBands {
+    // Contiguous bands split at the given frequencies
+    pub fn new(bass_cutoff : f64, treble_cutoff : f64) -> Self {
+        FrequencyBands {
+            bass : (20.0, bass_cutoff),
+            mid : (bass_cutoff, treble_cutoff),
+            treble : (treble_cutoff, 16000.0),
+        }
+    }
+}
+
+impl Default for FrequencyBands {
+    fn default() -> Self {
+        FrequencyBands::new(250.0, 4000.0)
+    }
+}
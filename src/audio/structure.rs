@@ -0,0 +1,207 @@
+// Finds where the sections of a song (verse, chorus, drop...) start by
+// looking for the points where what came before sounds least like what
+// comes after
+
+use std::cmp::Ordering;
+
+// Frames are averaged into blocks this long before comparing them
+const BLOCK_SECONDS : f64 = 0.5;
+
+// How far either side of a point we look when deciding if it's a boundary
+const KERNEL_SECONDS : f64 = 8.0;
+
+// Sections are at least this long, and this far from either end of the song
+const MIN_SECTION_SECONDS : f64 = 8.0;
+
+// How different the two sides of a boundary have to be, from 0 for no
+// change to 1 for completely unrelated
+const NOVELTY_THRESHOLD : f64 = 0.15;
+
+// Collects a description of the song as it goes past
+pub struct SectionFinder {
+    frames_per_block : usize,
+    // Features of each finished block
+    blocks : Vec<Vec<f64>>,
+    current : Vec<f64>,
+    current_frames : usize,
+}
+
+impl SectionFinder {
+    pub fn new(frame_rate : f64) -> Self {
+        SectionFinder {
+            frames_per_block : ((BLOCK_SECONDS * frame_rate).round() as usize).max(1),
+            blocks : Vec::new(),
+            current : Vec::new(),
+            current_frames : 0,
+        }
+    }
+
+    // Add a frame's spectrum and chroma
+    pub fn push(&mut self, bins : &[f32], chroma : &[f32]) {
+        if self.current.is_empty() {
+            self.current = vec![0.0; bins.len() + chroma.len()];
+        }
+
+        // Log energy so quiet bands count for something next to the bass
+        let features = bins.iter()
+                           .map(|&a| (1.0 + 1000.0 * a as f64).ln())
+                           .chain(chroma.iter().map(|&x| x as f64));
+        for (c, x) in self.current.iter_mut().zip(features) {
+            *c += x;
+        }
+
+        self.current_frames += 1;
+        if self.current_frames == self.frames_per_block {
+            self.end_block();
+        }
+    }
+
+    fn end_block(&mut self) {
+        let n = self.current_frames as f64;
+        let block = self.current.iter().map(|x| x / n).collect();
+        self.blocks.push(block);
+        self.current = Vec::new();
+        self.current_frames = 0;
+    }
+
+    // The first frame of every section, starting with 0
+    pub fn sections(mut self) -> Vec<usize> {
+        if self.current_frames > 0 {
+            self.end_block();
+        }
+
+        let features = standardise(&self.blocks);
+        let novelty = novelty(&features, (KERNEL_SECONDS / BLOCK_SECONDS) as usize);
+        let min_blocks = (MIN_SECTION_SECONDS / BLOCK_SECONDS) as usize;
+
+        // Strongest peaks first, skipping any too close to one we already have
+        let mut peaks = (1 .. novelty.len().saturating_sub(1))
+            .filter(|&b| novelty[b] > NOVELTY_THRESHOLD &&
+                         novelty[b] >= novelty[b - 1] &&
+                         novelty[b] > novelty[b + 1])
+            .filter(|&b| b >= min_blocks && b + min_blocks <= novelty.len())
+            .collect::<Vec<usize>>();
+        // Broken audio can leave NaNs in the features, which never pass the
+        // threshold above, but don't let them take the sort down either
+        peaks.sort_by(|&a, &b| novelty[b].partial_cmp(&novelty[a]).unwrap_or(Ordering::Equal));
+
+        let mut boundaries : Vec<usize> = Vec::new();
+        for b in peaks {
+            if boundaries.iter().all(|&x| (x as i64 - b as i64).abs() as usize >= min_blocks) {
+                boundaries.push(b);
+            }
+        }
+        boundaries.sort();
+
+        let mut starts = vec![0];
+        starts.extend(boundaries.iter().map(|&b| b * self.frames_per_block));
+        starts
+    }
+}
+
+// Scale each feature to zero mean and unit variance over the song so they
+// all count the same, then each block to unit length so comparing two is
+// just a dot product
+fn standardise(blocks : &[Vec<f64>]) -> Vec<Vec<f64>> {
+    if blocks.is_empty() {
+        return Vec::new();
+    }
+
+    let n = blocks.len() as f64;
+    let dims = blocks[0].len();
+    let mut mean = vec![0.0; dims];
+    let mut var = vec![0.0; dims];
+    for block in blocks.iter() {
+        for (m, x) in mean.iter_mut().zip(block.iter()) {
+            *m += x / n;
+        }
+    }
+    for block in blocks.iter() {
+        for ((v, m), x) in var.iter_mut().zip(mean.iter()).zip(block.iter()) {
+            *v += (x - m) * (x - m) / n;
+        }
+    }
+
+    blocks.iter().map(|block| {
+        let z = block.iter()
+                     .zip(mean.iter().zip(var.iter()))
+                     .map(|(x, (m, v))| if *v > 1e-12 { (x - m) / v.sqrt() } else { 0.0 })
+                     .collect::<Vec<f64>>();
+        let len = z.iter().fold(0.0, |acc, x| acc + x * x).sqrt();
+        if len > 0.0 { z.iter().map(|x| x / len).collect() } else { z }
+    }).collect()
+}
+
+// Slide a checkerboard over the diagonal of the self similarity matrix,
+// it scores high where the blocks either side are alike among themselves
+// but unlike each other. Tapered so the closest blocks count the most.
+fn novelty(features : &[Vec<f64>], half_width : usize) -> Vec<f64> {
+    let n = features.len() as i64;
+    let l = half_width as i64;
+    let dot = |a : &Vec<f64>, b : &Vec<f64>| a.iter().zip(b.iter()).fold(0.0, |acc, (x, y)| acc + x * y);
+    let taper = |i : i64| {
+        let x = (i as f64 + 0.5) / l as f64;
+        (-2.0 * x * x).exp()
+    };
+
+    (0 .. n).map(|b| {
+        let mut sum = 0.0;
+        let mut weight = 0.0;
+        // i and j are offsets from the boundary, negative before it
+        for i in -l .. l {
+            for j in -l .. l {
+                let (x, y) = (b + i, b + j);
+                if x < 0 || y < 0 || x >= n || y >= n {
+                    continue;
+                }
+                let w = taper(if i < 0 { -i - 1 } else { i }) * taper(if j < 0 { -j - 1 } else { j });
+                let sign = if (i < 0) == (j < 0) { 1.0 } else { -1.0 };
+                sum += sign * w * dot(&features[x as usize], &features[y as usize]);
+                weight += w;
+            }
+        }
+        // Similarities go from -1 to 1, so this is 1 for a clean cut
+        // between two things with nothing in common
+        if weight > 0.0 { sum / weight } else { 0.0 }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SectionFinder;
+    use common::AudioUpdate;
+    use audio::AudioProcessor;
+    use audio::spectrum::FrequencyBands;
+    use audio::synth::{SynthSong, Signal};
+
+    #[test]
+    fn two_halves() {
+        // 20 seconds of a steady tone then 20 of noise
+        let rate = 8000;
+        let halves = [Signal::Sweep { lo : 440.0, hi : 440.0 }, Signal::WhiteNoise];
+
+        let mut audio_proc = AudioProcessor::new(0.05, rate as f64, FrequencyBands::default());
+        let mut finder = SectionFinder::new(audio_proc.update_rate());
+        {
+            let mut push = |update : AudioUpdate| {
+                let bins = update.audio.spectrum.bins.iter().map(|&a| a as f32).collect::<Vec<f32>>();
+                let chroma = update.audio.chroma.iter().map(|&x| x as f32).collect::<Vec<f32>>();
+                finder.push(&bins, &chroma);
+            };
+            for &signal in halves.iter() {
+                for data in SynthSong::new(signal, 20.0, 0.5, rate, 1) {
+                    if let Some(update) = audio_proc.process_sample(&data.samples) {
+                        push(update);
+                    }
+                }
+            }
+            audio_proc.flush(push);
+        }
+
+        let middle = 20.0 * audio_proc.update_rate();
+        let starts = finder.sections();
+        assert_eq!(starts.len(), 2, "{:?}", starts);
+        assert_eq!(starts[0], 0);
+        assert!((starts[1] as f64 - middle).abs() < audio_proc.update_rate(), "{:?}", starts);
+    }
+}
@@ -0,0 +1,250 @@
+use std::f64::consts::PI;
+use audio::{Song, AudioData, from_seconds};
+use audio::playback::DecodedAudio;
+
+// How long each click of a click track lasts, in seconds
+const CLICK_SECONDS : f64 = 0.01;
+const CLICK_HZ : f64 = 1000.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    // A sine wave gliding from lo to hi, spending as long on each octave
+    Sweep { lo : f64, hi : f64 },
+    // A short beep on every beat
+    Click { bpm : f64 },
+    // The same energy at every frequency
+    WhiteNoise,
+    // The same energy in every octave, closer to what music is like
+    PinkNoise,
+    Silence,
+}
+
+// Test signals worked out as they're played, so scripts can be tried out
+// without any music and the analysis gets the same audio every time
+pub struct SynthSong {
+    signal : Signal,
+    seconds : f64,
+    amplitude : f64,
+    channels : usize,
+    sample_rate : u32,
+    current_sample : usize,
+    // Noise comes from a fixed seed so it's the same on every run
+    rng : u64,
+    // State of the filter that turns white noise pink
+    pink : [f64; 7],
+}
+
+impl SynthSong {
+    // Takes a spec of the form "signal,seconds=30,amp=0.5,rate=44100,channels=2"
+    // where the signal is one of sweep, click, noise, pink or silence.
+    // Sweeps also take lo=20 and hi=20000 in Hz, and clicks take bpm=120.
+    // The options can be in any order and default to the values above.
+    pub fn from_spec(spec : &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+
+        let mut seconds = 30.0;
+        let mut amplitude = 0.5;
+        let mut sample_rate = 44100;
+        let mut channels = 2;
+        let mut lo = 20.0;
+        let mut hi = 20000.0;
+        let mut bpm = 120.0;
+
+        for part in parts {
+            let mut kv = part.splitn(2, '=');
+            let key = kv.next().unwrap_or("").trim().to_lowercase();
+            let value = kv.next().unwrap_or("").trim();
+            // Infinities and NaN parse fine but aren't any use as a length or level
+            let number = || match value.parse::<f64>() {
+                Ok(x) if x.is_finite() => Ok(x),
+                _ => Err(format!("Invalid value for {}: {}", key, value)),
+            };
+            match key.as_ref() {
+                "seconds" => seconds = number()?,
+                "amp" => amplitude = number()?,
+                "rate" => {
+                    sample_rate = value.parse().map_err(|_| format!("Invalid sample rate: {}", value))?;
+                }
+                "channels" => {
+                    channels = value.parse().map_err(|_| format!("Invalid channel count: {}", value))?;
+                }
+                "lo" => lo = number()?,
+                "hi" => hi = number()?,
+                "bpm" => bpm = number()?,
+                x => return Err(format!("Unknown synth option: {}", x)),
+            }
+        }
+
+        let signal = match name.as_ref() {
+            "sweep" => Signal::Sweep { lo : lo, hi : hi },
+            "click" => Signal::Click { bpm : bpm },
+            "noise" | "white" => Signal::WhiteNoise,
+            "pink" => Signal::PinkNoise,
+            "silence" => Signal::Silence,
+            x => return Err(format!("Unknown synth signal: {}", x)),
+        };
+
+        if channels == 0 || sample_rate == 0 {
+            return Err(format!("Need at least one channel and a non zero sample rate"));
+        }
+        if seconds <= 0.0 {
+            return Err(format!("Synth audio needs to last longer than 0 seconds"));
+        }
+        if let Signal::Sweep { lo, hi } = signal {
+            if lo <= 0.0 || hi <= 0.0 {
+                return Err(format!("Sweep frequencies need to be above 0 Hz"));
+            }
+        }
+        if let Signal::Click { bpm } = signal {
+            if bpm <= 0.0 {
+                return Err(format!("Clicks need a bpm above 0"));
+            }
+        }
+
+        Ok(SynthSong::new(signal, seconds, amplitude, sample_rate, channels))
+    }
+
+    pub fn new(signal : Signal, seconds : f64, amplitude : f64, sample_rate : u32, channels : usize) -> Self {
+        SynthSong {
+            signal : signal,
+            seconds : seconds,
+            amplitude : amplitude,
+            channels : channels,
+            sample_rate : sample_rate,
+            current_sample : 0,
+            rng : 0x2545F4914F6CDD1D,
+            pink : [0.0; 7],
+        }
+    }
+
+    fn len(&self) -> usize {
+        (self.seconds * self.sample_rate as f64) as usize
+    }
+
+    // The next sample, before scaling by the amplitude
+    fn sample(&mut self, t : f64) -> f64 {
+        match self.signal {
+            Signal::Sweep { lo, hi } => {
+                // The phase of an exponential sweep, so the frequency at t
+                // is lo * (hi / lo)^(t / seconds)
+                let k = (hi / lo).ln() / self.seconds;
+                let phase = if k.abs() > 1e-12 {
+                    2.0 * PI * lo * ((k * t).exp() - 1.0) / k
+                }
+                else {
+                    2.0 * PI * lo * t
+                };
+                phase.sin()
+            }
+            Signal::Click { bpm } => {
+                let since_beat = t % (60.0 / bpm);
+                if since_beat < CLICK_SECONDS {
+                    (2.0 * PI * CLICK_HZ * since_beat).sin()
+                }
+                else {
+                    0.0
+                }
+            }
+            Signal::WhiteNoise => self.white(),
+            Signal::PinkNoise => {
+                // Paul Kellet's filter, a sum of one pole filters spread over
+                // the octaves giving -3dB each octave
+                let w = self.white();
+                let p = &mut self.pink;
+                p[0] = 0.99886 * p[0] + w * 0.0555179;
+                p[1] = 0.99332 * p[1] + w * 0.0750759;
+                p[2] = 0.96900 * p[2] + w * 0.1538520;
+                p[3] = 0.86650 * p[3] + w * 0.3104856;
+                p[4] = 0.55000 * p[4] + w * 0.5329522;
+                p[5] = -0.7616 * p[5] - w * 0.0168980;
+                let pink = p[0] + p[1] + p[2] + p[3] + p[4] + p[5] + p[6] + w * 0.5362;
+                p[6] = w * 0.115926;
+                // Roughly back to the range of the white noise
+                pink * 0.11
+            }
+            Signal::Silence => 0.0,
+        }
+    }
+
+    // Uniform in -1..1, from xorshift64*
+    fn white(&mut self) -> f64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let x = self.rng.wrapping_mul(0x2545F4914F6CDD1D);
+        (x >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+}
+
+impl Song for SynthSong {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn decode(&self) -> Option<DecodedAudio> {
+        // Generate it all again from the start for the output
+        let mut song = SynthSong::new(self.signal, self.seconds, self.amplitude, self.sample_rate, self.channels);
+        Some(DecodedAudio::from_song(&mut song))
+    }
+}
+
+impl Iterator for SynthSong {
+    type Item = AudioData;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current_sample >= self.len() {
+            return None;
+        }
+
+        let t = self.current_sample as f64 / self.sample_rate as f64;
+        let x = (self.sample(t) * self.amplitude).max(-1.0).min(1.0) as f32;
+
+        let dur = from_seconds(t);
+        self.current_sample += 1;
+        Some(AudioData {
+            time : dur,
+            samples : vec![x; self.channels],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::AudioType;
+    use audio::AudioProcessor;
+    use audio::spectrum::FrequencyBands;
+    use super::SynthSong;
+
+    // Play a synth spec through the analysis, returning the last value of a component
+    fn last(spec : &str, t : AudioType) -> f64 {
+        let song = SynthSong::from_spec(spec).unwrap();
+        let mut audio_proc = AudioProcessor::new(0.05, song.sample_rate as f64, FrequencyBands::default());
+        let mut x = None;
+        for data in song {
+            if let Some(update) = audio_proc.process_sample(&data.samples) {
+                x = update.audio.components.get(&t).cloned();
+            }
+        }
+        x.unwrap()
+    }
+
+    #[test]
+    fn click_bpm() {
+        let bpm = last("click,bpm=120,seconds=20,rate=8000,channels=1", AudioType::Bpm);
+        assert!((bpm - 120.0).abs() < 2.0, "{}", bpm);
+    }
+
+    #[test]
+    fn silence_level() {
+        let level = last("silence,seconds=2,rate=8000,channels=1", AudioType::Level);
+        assert!(level.abs() < 1e-6, "{}", level);
+    }
+
+    #[test]
+    fn rejects_non_finite() {
+        for spec in ["sweep,seconds=nan", "noise,seconds=inf", "noise,amp=NaN", "sweep,lo=-inf", "click,bpm=inf"].iter() {
+            assert!(SynthSong::from_spec(spec).is_err(), "{}", spec);
+        }
+        assert!(SynthSong::from_spec("sweep,seconds=1.5,amp=0.25").is_ok());
+    }
+}
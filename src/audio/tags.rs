@@ -0,0 +1,636 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, BufReader};
+use std::path::Path;
+use audio::{sniff_format, Format};
+
+// Don't read more than this looking for tags, cover art can make them huge
+const MAX_TAG_BYTES : usize = 16 * 1024 * 1024;
+
+// The genres ID3v1 numbers, which ID3v2 can refer to as well
+const ID3_GENRES : [&'static str; 80] = [
+    "Blues", "Classic Rock", "Country", "Dance", "Disco", "Funk", "Grunge", "Hip-Hop",
+    "Jazz", "Metal", "New Age", "Oldies", "Other", "Pop", "R&B", "Rap",
+    "Reggae", "Rock", "Techno", "Industrial", "Alternative", "Ska", "Death Metal", "Pranks",
+    "Soundtrack", "Euro-Techno", "Ambient", "Trip-Hop", "Vocal", "Jazz+Funk", "Fusion", "Trance",
+    "Classical", "Instrumental", "Acid", "House", "Game", "Sound Clip", "Gospel", "Noise",
+    "AlternRock", "Bass", "Soul", "Punk", "Space", "Meditative", "Instrumental Pop", "Instrumental Rock",
+    "Ethnic", "Gothic", "Darkwave", "Techno-Industrial", "Electronic", "Pop-Folk", "Eurodance", "Dream",
+    "Southern Rock", "Comedy", "Cult", "Gangsta", "Top 40", "Christian Rap", "Pop/Funk", "Jungle",
+    "Native American", "Cabaret", "New Wave", "Psychadelic", "Rave", "Showtunes", "Trailer", "Lo-Fi",
+    "Tribal", "Acid Punk", "Acid Jazz", "Polka", "Retro", "Musical", "Rock & Roll", "Hard Rock",
+];
+
+// What a song says about itself. Fields are named the same whatever the
+// format: title, artist, album, genre, year and track, plus anything else
+// Vorbis comments have under their own lower case names.
+#[derive(Clone, Debug, Default)]
+pub struct Tags {
+    fields : HashMap<String, String>,
+}
+
+impl Tags {
+    // Songs without tags, or that we can't read, just have no fields
+    pub fn read(path : &Path) -> Self {
+        let mut tags = Tags::default();
+        let result = match sniff_format(path) {
+            Some(Format::Mp3) => tags.read_mp3(path),
+            Some(Format::Flac) => tags.read_flac(path),
+            Some(Format::Ogg) => tags.read_ogg(path),
+            Some(Format::Wav) => tags.read_wav(path),
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            println!("Couldn't read the tags of {}: {}", path.display(), e);
+        }
+        tags
+    }
+
+    // The tags to show on screen, songs without a title go by their file name
+    pub fn for_display(path : &Path) -> Self {
+        let mut tags = Tags::read(path);
+        if let Some(name) = path.file_stem().and_then(|x| x.to_str()) {
+            tags.insert("title", name);
+        }
+        tags
+    }
+
+    pub fn get(&self, field : &str) -> Option<&str> {
+        self.fields.get(&field.to_lowercase()).map(|x| x.as_str())
+    }
+
+    // Earlier values win, so the better kind of tag is read first
+    fn insert(&mut self, field : &str, value : &str) {
+        let value = value.trim_matches(|c : char| c == '\0' || c.is_whitespace());
+        if !value.is_empty() && !self.fields.contains_key(field) {
+            self.fields.insert(field.to_string(), value.to_string());
+        }
+    }
+
+    fn read_mp3(&mut self, path : &Path) -> io::Result<()> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut header = [0u8; 10];
+        file.read_exact(&mut header)?;
+        // A tag too big to bother with, or cut short, can still leave an
+        // ID3v1 tag to fall back on
+        if &header[0 .. 3] == b"ID3" {
+            let size = synchsafe(&header[6 .. 10]);
+            if size <= MAX_TAG_BYTES {
+                let mut body = Vec::new();
+                (&mut file).take(size as u64).read_to_end(&mut body)?;
+                self.read_id3v2(header[3], header[5], &body);
+            }
+        }
+
+        // The old kind of tag on the end, for anything the new kind didn't have
+        let mut file = file.into_inner();
+        if file.seek(SeekFrom::End(-128)).is_ok() {
+            let mut v1 = [0u8; 128];
+            if file.read_exact(&mut v1).is_ok() && &v1[0 .. 3] == b"TAG" {
+                self.read_id3v1(&v1);
+            }
+        }
+        Ok(())
+    }
+
+    // The body of an ID3v2 tag, after the 10 byte header
+    fn read_id3v2(&mut self, version : u8, flags : u8, body : &[u8]) {
+        // Version 4 does this frame by frame instead, which is rare enough to skip
+        let body = if flags & 0x80 != 0 && version < 4 { unsynchronise(body) } else { body.to_vec() };
+
+        let mut pos = 0;
+        if flags & 0x40 != 0 && body.len() >= 4 {
+            // Skip the extended header
+            pos = match version {
+                3 => 4 + be_u32(&body[0 .. 4]),
+                4 => synchsafe(&body[0 .. 4]),
+                _ => 0,
+            };
+        }
+
+        let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+        while pos + header_len <= body.len() {
+            let id = &body[pos .. pos + id_len];
+            // Padding
+            if id[0] == 0 {
+                break;
+            }
+            let size = match version {
+                2 => ((body[pos + 3] as usize) << 16) | ((body[pos + 4] as usize) << 8) | body[pos + 5] as usize,
+                3 => be_u32(&body[pos + 4 .. pos + 8]),
+                _ => synchsafe(&body[pos + 4 .. pos + 8]),
+            };
+            let start = pos + header_len;
+            if start + size > body.len() {
+                break;
+            }
+
+            let field = match id {
+                b"TIT2" | b"TT2" => Some("title"),
+                b"TPE1" | b"TP1" => Some("artist"),
+                b"TALB" | b"TAL" => Some("album"),
+                b"TCON" | b"TCO" => Some("genre"),
+                b"TDRC" | b"TYER" | b"TYE" => Some("year"),
+                b"TRCK" | b"TRK" => Some("track"),
+                _ => None,
+            };
+            if let Some(field) = field {
+                let text = id3_text(&body[start .. start + size]);
+                let text = if field == "genre" { id3_genre(&text) } else { text };
+                self.insert(field, &text);
+            }
+
+            pos = start + size;
+        }
+    }
+
+    fn read_id3v1(&mut self, tag : &[u8]) {
+        self.insert("title", &latin1(&tag[3 .. 33]));
+        self.insert("artist", &latin1(&tag[33 .. 63]));
+        self.insert("album", &latin1(&tag[63 .. 93]));
+        self.insert("year", &latin1(&tag[93 .. 97]));
+        if let Some(genre) = ID3_GENRES.get(tag[127] as usize) {
+            self.insert("genre", genre);
+        }
+    }
+
+    fn read_flac(&mut self, path : &Path) -> io::Result<()> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)?;
+
+        loop {
+            let mut header = [0u8; 4];
+            file.read_exact(&mut header)?;
+            let last = header[0] & 0x80 != 0;
+            let size = ((header[1] as usize) << 16) | ((header[2] as usize) << 8) | header[3] as usize;
+
+            // Block type 4 holds the Vorbis comments
+            if header[0] & 0x7F == 4 {
+                let block = read_bytes(&mut file, size)?;
+                self.read_vorbis_comments(&block);
+                return Ok(());
+            }
+            if last {
+                return Ok(());
+            }
+            file.seek(SeekFrom::Current(size as i64))?;
+        }
+    }
+
+    fn read_ogg(&mut self, path : &Path) -> io::Result<()> {
+        // The comments are the second packet of the stream, after the one
+        // saying what the stream is
+        let packets = ogg_packets(&mut BufReader::new(File::open(path)?), 2)?;
+        if let Some(comments) = packets.get(1) {
+            if comments.starts_with(b"\x03vorbis") {
+                self.read_vorbis_comments(&comments[7 ..]);
+            }
+            else if comments.starts_with(b"OpusTags") {
+                self.read_vorbis_comments(&comments[8 ..]);
+            }
+        }
+        Ok(())
+    }
+
+    // A list of FIELD=value pairs after the name of the encoder, everything
+    // is little endian
+    fn read_vorbis_comments(&mut self, block : &[u8]) {
+        let mut pos = 0;
+        let mut next = |len : usize| -> Option<&[u8]> {
+            if pos + len > block.len() {
+                return None;
+            }
+            pos += len;
+            Some(&block[pos - len .. pos])
+        };
+
+        let vendor_len = match next(4) { Some(x) => le_u32(x), None => return };
+        if next(vendor_len).is_none() {
+            return;
+        }
+        let count = match next(4) { Some(x) => le_u32(x), None => return };
+
+        for _ in 0 .. count {
+            let len = match next(4) { Some(x) => le_u32(x), None => return };
+            let comment = match next(len) { Some(x) => String::from_utf8_lossy(x).into_owned(), None => return };
+            let mut kv = comment.splitn(2, '=');
+            let key = kv.next().unwrap_or("").to_lowercase();
+            let value = kv.next().unwrap_or("");
+            let field = match key.as_ref() {
+                "date" => "year",
+                "tracknumber" => "track",
+                x => x,
+            };
+            self.insert(field, value);
+        }
+    }
+
+    fn read_wav(&mut self, path : &Path) -> io::Result<()> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut riff = [0u8; 12];
+        file.read_exact(&mut riff)?;
+
+        // Go through the chunks until the end of the file
+        let mut header = [0u8; 8];
+        while file.read_exact(&mut header).is_ok() {
+            let size = le_u32(&header[4 .. 8]);
+            // Chunks are padded to an even length
+            let padded = size + (size & 1);
+
+            match &header[0 .. 4] {
+                b"LIST" => {
+                    let list = read_bytes(&mut file, padded)?;
+                    if list.starts_with(b"INFO") {
+                        self.read_wav_info(&list[4 ..]);
+                    }
+                }
+                b"id3 " | b"ID3 " => {
+                    let tag = read_bytes(&mut file, padded)?;
+                    if tag.len() >= 10 && &tag[0 .. 3] == b"ID3" {
+                        self.read_id3v2(tag[3], tag[5], &tag[10 ..]);
+                    }
+                }
+                _ => {
+                    file.seek(SeekFrom::Current(padded as i64))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // A list of subchunks each holding one null terminated string
+    fn read_wav_info(&mut self, info : &[u8]) {
+        let mut pos = 0;
+        while pos + 8 <= info.len() {
+            let size = le_u32(&info[pos + 4 .. pos + 8]);
+            let start = pos + 8;
+            if start + size > info.len() {
+                break;
+            }
+            let field = match &info[pos .. pos + 4] {
+                b"INAM" => Some("title"),
+                b"IART" => Some("artist"),
+                b"IPRD" => Some("album"),
+                b"IGNR" => Some("genre"),
+                b"ICRD" => Some("year"),
+                b"ITRK" | b"IPRT" => Some("track"),
+                _ => None,
+            };
+            if let Some(field) = field {
+                self.insert(field, &String::from_utf8_lossy(&info[start .. start + size]));
+            }
+            pos = start + size + (size & 1);
+        }
+    }
+}
+
+// The first few packets of the first stream in an ogg file, put back
+// together from the pages they are split over
+fn ogg_packets<R : Read>(r : &mut R, wanted : usize) -> io::Result<Vec<Vec<u8>>> {
+    let mut packets = Vec::new();
+    let mut packet = Vec::new();
+    let mut serial = None;
+    let mut read = 0;
+
+    while packets.len() < wanted && read < MAX_TAG_BYTES {
+        let mut header = [0u8; 27];
+        r.read_exact(&mut header)?;
+        if &header[0 .. 4] != b"OggS" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "lost track of the ogg pages"));
+        }
+        let page_serial = le_u32(&header[14 .. 18]);
+        let mut lacing = vec![0u8; header[26] as usize];
+        r.read_exact(&mut lacing)?;
+        let body = read_bytes(r, lacing.iter().map(|&x| x as usize).sum())?;
+        read += body.len();
+
+        // Only follow the first stream if there are several mixed together
+        if *serial.get_or_insert(page_serial) != page_serial {
+            continue;
+        }
+
+        let mut pos = 0;
+        for &len in lacing.iter() {
+            packet.extend_from_slice(&body[pos .. pos + len as usize]);
+            pos += len as usize;
+            // A segment shorter than the most it can be ends the packet
+            if len < 255 {
+                packets.push(packet);
+                packet = Vec::new();
+            }
+        }
+    }
+    Ok(packets)
+}
+
+fn read_bytes<R : Read>(r : &mut R, len : usize) -> io::Result<Vec<u8>> {
+    if len > MAX_TAG_BYTES {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "tag is too big"));
+    }
+    let mut buf = vec![0u8; len];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// Sizes in ID3 headers only use 7 bits of each byte
+fn synchsafe(b : &[u8]) -> usize {
+    b.iter().fold(0, |acc, &x| (acc << 7) | (x & 0x7F) as usize)
+}
+
+fn be_u32(b : &[u8]) -> usize {
+    b.iter().fold(0, |acc, &x| (acc << 8) | x as usize)
+}
+
+fn le_u32(b : &[u8]) -> usize {
+    b.iter().rev().fold(0, |acc, &x| (acc << 8) | x as usize)
+}
+
+// Undo the zero bytes put after every 0xFF so nothing looks like an mpeg sync
+fn unsynchronise(b : &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(b.len());
+    for (i, &x) in b.iter().enumerate() {
+        if !(x == 0 && i > 0 && b[i - 1] == 0xFF) {
+            out.push(x);
+        }
+    }
+    out
+}
+
+fn latin1(b : &[u8]) -> String {
+    b.iter().take_while(|&&x| x != 0).map(|&x| x as char).collect()
+}
+
+// A text frame starts with a byte saying how the rest is encoded. Only the
+// first value is kept when a frame has several.
+fn id3_text(frame : &[u8]) -> String {
+    if frame.is_empty() {
+        return String::new();
+    }
+    let text = &frame[1 ..];
+    match frame[0] {
+        0 => latin1(text),
+        1 | 2 => {
+            // UTF-16, big endian unless the byte order mark says otherwise
+            let little = frame[0] == 1 && text.starts_with(&[0xFF, 0xFE]);
+            let text = if frame[0] == 1 && text.len() >= 2 { &text[2 ..] } else { text };
+            let units = text.chunks(2)
+                            .filter(|c| c.len() == 2)
+                            .map(|c| if little { c[0] as u16 | (c[1] as u16) << 8 } else { (c[0] as u16) << 8 | c[1] as u16 })
+                            .take_while(|&u| u != 0)
+                            .collect::<Vec<u16>>();
+            String::from_utf16_lossy(&units)
+        }
+        _ => {
+            let end = text.iter().position(|&x| x == 0).unwrap_or(text.len());
+            String::from_utf8_lossy(&text[.. end]).into_owned()
+        }
+    }
+}
+
+// Genres can be an ID3v1 number, on its own or in brackets
+fn id3_genre(text : &str) -> String {
+    let number = text.trim_left_matches('(').split(')').next().unwrap_or("");
+    match number.parse::<usize>().ok().and_then(|n| ID3_GENRES.get(n)) {
+        Some(genre) if text.starts_with('(') || text == number => genre.to_string(),
+        _ => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use super::Tags;
+
+    // Tags are read from files, so write the bytes out to read them back
+    fn read(name : &str, bytes : &[u8]) -> Tags {
+        let path = env::temp_dir().join(format!("audisuals-tags-{}", name));
+        File::create(&path).and_then(|mut f| f.write_all(bytes)).unwrap();
+        let tags = Tags::read(&path);
+        fs::remove_file(&path).unwrap();
+        tags
+    }
+
+    fn id3v1(title : &str, artist : &str, album : &str, year : &str, genre : u8) -> Vec<u8> {
+        let mut tag = b"TAG".to_vec();
+        for &(text, len) in [(title, 30), (artist, 30), (album, 30), (year, 4), ("", 30)].iter() {
+            let mut field = text.as_bytes().to_vec();
+            field.resize(len, 0);
+            tag.extend_from_slice(&field);
+        }
+        tag.push(genre);
+        tag
+    }
+
+    fn synchsafe(x : usize) -> Vec<u8> {
+        vec![(x >> 21) as u8 & 0x7F, (x >> 14) as u8 & 0x7F, (x >> 7) as u8 & 0x7F, x as u8 & 0x7F]
+    }
+
+    // A version 3 frame, sizes are plain big endian
+    fn id3v2_frame(id : &[u8], body : &[u8]) -> Vec<u8> {
+        let len = body.len();
+        let mut frame = id.to_vec();
+        frame.extend_from_slice(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8, 0, 0]);
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    // An ID3v2.3 header saying the tag is size bytes long
+    fn id3v2_header(size : usize) -> Vec<u8> {
+        let mut header = b"ID3\x03\x00\x00".to_vec();
+        header.extend_from_slice(&synchsafe(size));
+        header
+    }
+
+    // Something like an mpeg frame for the audio
+    fn mpeg() -> Vec<u8> {
+        let mut audio = vec![0xFF, 0xFB, 0x90, 0x00];
+        audio.resize(64, 0);
+        audio
+    }
+
+    fn le_u32(x : usize) -> Vec<u8> {
+        vec![x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8]
+    }
+
+    fn vorbis_comments(comments : &[&str]) -> Vec<u8> {
+        let mut block = le_u32(4);
+        block.extend_from_slice(b"test");
+        block.extend_from_slice(&le_u32(comments.len()));
+        for c in comments.iter() {
+            block.extend_from_slice(&le_u32(c.len()));
+            block.extend_from_slice(c.as_bytes());
+        }
+        block
+    }
+
+    // A page holding one packet shorter than 255 bytes
+    fn ogg_page(sequence : u8, packet : &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\x00".to_vec();
+        page.push(if sequence == 0 { 2 } else { 0 });
+        page.extend_from_slice(&[0; 8]);
+        page.extend_from_slice(&[1, 0, 0, 0]);
+        page.extend_from_slice(&[sequence, 0, 0, 0]);
+        page.extend_from_slice(&[0; 4]);
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend_from_slice(packet);
+        page
+    }
+
+    fn riff_chunk(id : &[u8], body : &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&le_u32(body.len()));
+        chunk.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    #[test]
+    fn id3v1_only() {
+        let mut file = mpeg();
+        file.extend(id3v1("Old Title", "Old Artist", "Old Album", "1999", 17));
+        let tags = read("v1.mp3", &file);
+        assert_eq!(tags.get("title"), Some("Old Title"));
+        assert_eq!(tags.get("artist"), Some("Old Artist"));
+        assert_eq!(tags.get("album"), Some("Old Album"));
+        assert_eq!(tags.get("year"), Some("1999"));
+        assert_eq!(tags.get("genre"), Some("Rock"));
+    }
+
+    #[test]
+    fn id3v2_before_id3v1() {
+        let mut frames = id3v2_frame(b"TIT2", b"\x00New Title");
+        // UTF-16 with a little endian byte order mark
+        let artist = "Ärtist".encode_utf16().fold(vec![1, 0xFF, 0xFE], |mut acc, u| {
+            acc.push(u as u8);
+            acc.push((u >> 8) as u8);
+            acc
+        });
+        frames.extend(id3v2_frame(b"TPE1", &artist));
+        frames.extend(id3v2_frame(b"TCON", b"\x00(17)"));
+        frames.extend_from_slice(&[0; 16]);
+
+        let mut file = id3v2_header(frames.len());
+        file.extend(frames);
+        file.extend(mpeg());
+        file.extend(id3v1("Old Title", "Old Artist", "Old Album", "1999", 0));
+
+        let tags = read("v2.mp3", &file);
+        assert_eq!(tags.get("title"), Some("New Title"));
+        assert_eq!(tags.get("artist"), Some("Ärtist"));
+        assert_eq!(tags.get("genre"), Some("Rock"));
+        // Only in the old tag
+        assert_eq!(tags.get("album"), Some("Old Album"));
+    }
+
+    #[test]
+    fn id3v2_truncated_frame() {
+        // The second frame says it's longer than what's left of the tag
+        let mut frames = id3v2_frame(b"TIT2", b"\x00Title");
+        let mut album = id3v2_frame(b"TALB", b"\x00Album");
+        album[7] = 200;
+        frames.extend(album);
+
+        let mut file = id3v2_header(frames.len());
+        file.extend(frames);
+        file.extend(mpeg());
+
+        let tags = read("truncated.mp3", &file);
+        assert_eq!(tags.get("title"), Some("Title"));
+        assert_eq!(tags.get("album"), None);
+    }
+
+    #[test]
+    fn id3v2_bigger_than_file() {
+        let frames = id3v2_frame(b"TIT2", b"\x00Title");
+        let mut file = id3v2_header(100_000);
+        file.extend(frames);
+        file.extend(mpeg());
+        file.extend(id3v1("", "Old Artist", "", "", 255));
+
+        let tags = read("short.mp3", &file);
+        assert_eq!(tags.get("title"), Some("Title"));
+        assert_eq!(tags.get("artist"), Some("Old Artist"));
+    }
+
+    #[test]
+    fn id3v2_too_big_to_read() {
+        let mut file = id3v2_header(0x0FFF_FFFF);
+        file.extend(id3v2_frame(b"TIT2", b"\x00Title"));
+        file.extend(mpeg());
+        file.extend(id3v1("Old Title", "", "", "", 255));
+
+        let tags = read("huge.mp3", &file);
+        assert_eq!(tags.get("title"), Some("Old Title"));
+    }
+
+    #[test]
+    fn flac_comments() {
+        let mut file = b"fLaC".to_vec();
+        // Stream info, then the comments as the last block
+        file.extend_from_slice(&[0, 0, 0, 34]);
+        file.extend_from_slice(&[0; 34]);
+        let comments = vorbis_comments(&["TITLE=Flac Song", "Artist=Someone", "DATE=2001", "TRACKNUMBER=3"]);
+        file.extend_from_slice(&[0x84, 0, 0, comments.len() as u8]);
+        file.extend(comments);
+
+        let tags = read("c.flac", &file);
+        assert_eq!(tags.get("title"), Some("Flac Song"));
+        assert_eq!(tags.get("artist"), Some("Someone"));
+        assert_eq!(tags.get("year"), Some("2001"));
+        assert_eq!(tags.get("track"), Some("3"));
+    }
+
+    #[test]
+    fn truncated_flac_comments() {
+        // Says there are two comments but only has one
+        let mut comments = vorbis_comments(&["TITLE=Flac Song"]);
+        comments[8] = 2;
+        let mut file = b"fLaC".to_vec();
+        file.extend_from_slice(&[0x84, 0, 0, comments.len() as u8]);
+        file.extend(comments);
+
+        let tags = read("d.flac", &file);
+        assert_eq!(tags.get("title"), Some("Flac Song"));
+    }
+
+    #[test]
+    fn ogg_comments() {
+        let mut file = ogg_page(0, b"\x01vorbis\x00\x00\x00\x00\x02\x44\xAC\x00\x00");
+        let mut packet = b"\x03vorbis".to_vec();
+        packet.extend(vorbis_comments(&["TITLE=Ogg Song", "ALBUM=Record"]));
+        packet.push(1);
+        file.extend(ogg_page(1, &packet));
+
+        let tags = read("e.ogg", &file);
+        assert_eq!(tags.get("title"), Some("Ogg Song"));
+        assert_eq!(tags.get("album"), Some("Record"));
+    }
+
+    #[test]
+    fn wav_info() {
+        let mut info = b"INFO".to_vec();
+        // An odd length, so padded
+        info.extend(riff_chunk(b"INAM", b"Wav Song\0"));
+        info.extend(riff_chunk(b"IART", b"Band\0"));
+        info.extend(riff_chunk(b"ICRD", b"2010\0"));
+
+        let mut body = b"WAVE".to_vec();
+        body.extend(riff_chunk(b"fmt ", &[1, 0, 1, 0, 0x40, 0x1F, 0, 0, 0x80, 0x3E, 0, 0, 2, 0, 16, 0]));
+        body.extend(riff_chunk(b"LIST", &info));
+        body.extend(riff_chunk(b"data", &[0; 8]));
+        let mut file = b"RIFF".to_vec();
+        file.extend(le_u32(body.len()));
+        file.extend(body);
+
+        let tags = read("f.wav", &file);
+        assert_eq!(tags.get("title"), Some("Wav Song"));
+        assert_eq!(tags.get("artist"), Some("Band"));
+        assert_eq!(tags.get("year"), Some("2010"));
+    }
+}
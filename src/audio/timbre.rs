@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use common::AudioType;
+use audio::spectrum::Spectrum;
+
+// Only this range is looked at, outside it is mostly rumble and hiss
+const LO_HZ : f64 = 20.0;
+const HI_HZ : f64 = 16000.0;
+
+// Brightness and Rolloff go from 0 to 1 between these frequencies,
+// on a log scale so each octave counts the same
+const SCALE_LO_HZ : f64 = 100.0;
+const SCALE_HI_HZ : f64 = 10000.0;
+
+// Rolloff is the frequency this fraction of the energy is below
+const ROLLOFF_FRACTION : f64 = 0.85;
+
+// The flatness of white noise. The power in each bin of noise is
+// exponentially distributed, so the geometric mean is e^-γ of the mean.
+const NOISE_FLATNESS : f64 = 0.5615;
+
+// Empty bins are counted as this fraction of the total power, so a few of
+// them don't take the geometric mean to 0 however loud the rest are
+const FLOOR : f64 = 1e-12;
+
+// Works out descriptors of what the audio sounds like from its spectrum
+pub struct Timbre {
+    prev_bins : Vec<f64>,
+}
+
+impl Timbre {
+    pub fn new() -> Self {
+        Timbre {
+            prev_bins : Vec::new(),
+        }
+    }
+
+    // Adds Brightness, Flux, Rolloff and Noisiness, all between 0 and 1
+    pub fn update(&mut self, spectrum : &Spectrum, audio_map : &mut HashMap<AudioType, f64>) {
+        // Power of the bins we look at, with their frequencies
+        let bins = spectrum.bins.iter()
+                                .zip(spectrum.centres.iter())
+                                .filter(|&(_, &c)| c >= LO_HZ && c < HI_HZ)
+                                .map(|(&a, &c)| (a * a, c))
+                                .collect::<Vec<(f64, f64)>>();
+        let total = bins.iter().fold(0.0, |acc, &(p, _)| acc + p);
+
+        let flux = self.flux(spectrum);
+        audio_map.insert(AudioType::Flux, flux);
+
+        // Only true silence has no shape to describe, however quiet the audio is
+        if total <= 0.0 {
+            audio_map.insert(AudioType::Brightness, 0.0);
+            audio_map.insert(AudioType::Rolloff, 0.0);
+            audio_map.insert(AudioType::Noisiness, 0.0);
+            return;
+        }
+
+        let centroid = bins.iter().fold(0.0, |acc, &(p, c)| acc + p * c) / total;
+        audio_map.insert(AudioType::Brightness, log_scale(centroid));
+
+        let mut below = 0.0;
+        let mut rolloff = HI_HZ;
+        for &(p, c) in bins.iter() {
+            below += p;
+            if below >= ROLLOFF_FRACTION * total {
+                rolloff = c;
+                break;
+            }
+        }
+        audio_map.insert(AudioType::Rolloff, log_scale(rolloff));
+
+        // Geometric mean over arithmetic mean, 1 for a flat spectrum and
+        // near 0 for a few pure tones
+        let n = bins.len() as f64;
+        let log_sum = bins.iter().fold(0.0, |acc, &(p, _)| acc + p.max(total * FLOOR).ln());
+        let flatness = (log_sum / n).exp() / (total / n);
+        audio_map.insert(AudioType::Noisiness, (flatness / NOISE_FLATNESS).min(1.0));
+    }
+
+    // How much of the spectrum is new since last time, the increase in each
+    // bin as a fraction of all there is now
+    fn flux(&mut self, spectrum : &Spectrum) -> f64 {
+        let flux = if self.prev_bins.len() == spectrum.bins.len() {
+            let (rise, total) = spectrum.bins.iter()
+                                             .zip(self.prev_bins.iter())
+                                             .fold((0.0, 0.0), |(rise, total), (a, b)| {
+                                                 (rise + (a - b).max(0.0), total + a)
+                                             });
+            if total > 0.0 { rise / total } else { 0.0 }
+        }
+        else {
+            0.0
+        };
+
+        self.prev_bins.clear();
+        self.prev_bins.extend(spectrum.bins.iter().cloned());
+        flux
+    }
+}
+
+// Place a frequency between SCALE_LO_HZ and SCALE_HI_HZ as 0 to 1
+fn log_scale(hz : f64) -> f64 {
+    let x = (hz / SCALE_LO_HZ).ln() / (SCALE_HI_HZ / SCALE_LO_HZ).ln();
+    x.max(0.0).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::f64::consts::PI;
+    use common::AudioType;
+    use audio::spectrum::SpectrumAnalyser;
+    use audio::synth::{SynthSong, Signal};
+    use super::Timbre;
+
+    const RATE : f64 = 44100.0;
+
+    fn noisiness(samples : &[f64]) -> f64 {
+        let mut analyser = SpectrumAnalyser::new(samples.len(), RATE);
+        let mut audio_map = HashMap::new();
+        Timbre::new().update(&analyser.analyse(samples), &mut audio_map);
+        audio_map[&AudioType::Noisiness]
+    }
+
+    #[test]
+    fn quiet_sine() {
+        // -60 dBFS
+        let samples = (0 .. 2048).map(|i| 0.001 * (2.0 * PI * 1000.0 * i as f64 / RATE).sin())
+                                 .collect::<Vec<f64>>();
+        let x = noisiness(&samples);
+        assert!(x < 0.1, "{}", x);
+    }
+
+    #[test]
+    fn quiet_noise() {
+        let samples = SynthSong::new(Signal::WhiteNoise, 1.0, 0.001, RATE as u32, 1)
+                          .take(2048)
+                          .map(|data| data.samples[0] as f64)
+                          .collect::<Vec<f64>>();
+        let x = noisiness(&samples);
+        assert!(x > 0.8, "{}", x);
+    }
+
+    #[test]
+    fn silence() {
+        assert_eq!(noisiness(&[0.0; 2048]), 0.0);
+    }
+}
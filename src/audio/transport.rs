@@ -0,0 +1,113 @@
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
+use common::{AudioPacket, AudioSettings, TransportPacket};
+use clock::Clock;
+use audio::{try_send_update, seconds, from_seconds};
+
+// Handles the playback controls sent from the window
+pub struct Transport {
+    rx : Receiver<TransportPacket>,
+    clock : Arc<Clock>,
+    // Where the current song starts on the clock
+    start : Duration,
+    // Length of the song, None if it's live
+    length : Option<Duration>,
+    paused : bool,
+    // Live audio has to be seeked by the song itself, so this waits for
+    // whoever is reading it
+    live_seek : Option<Duration>,
+}
+
+impl Transport {
+    // Starts off controlling live audio, until there is a song to control
+    pub fn new(rx : Receiver<TransportPacket>, clock : Arc<Clock>) -> Self {
+        Transport {
+            rx : rx,
+            clock : clock,
+            start : Duration::new(0, 0),
+            length : None,
+            paused : false,
+            live_seek : None,
+        }
+    }
+
+    // Control the song playing from start, seeks stay within it
+    pub fn set_song(&mut self, start : Duration, length : Duration) {
+        self.start = start;
+        self.length = Some(length);
+    }
+
+    // Deal with any pending controls, blocking for as long as we are paused.
+    // Returns the newest settings if they changed.
+    pub fn poll(&mut self, tx : &Sender<AudioPacket>) -> Option<AudioSettings> {
+        let mut settings = None;
+        loop {
+            let packet = if self.paused {
+                self.rx.recv().ok()
+            }
+            else {
+                self.rx.try_recv().ok()
+            };
+
+            let packet = match packet {
+                Some(TransportPacket::Settings(s)) => {
+                    settings = Some(s);
+                    continue;
+                }
+                Some(x) => x,
+                None => break,
+            };
+
+            let length = match self.length {
+                Some(x) => x,
+                None => {
+                    self.live_control(packet);
+                    continue;
+                }
+            };
+
+            match packet {
+                TransportPacket::TogglePause => {
+                    self.paused = !self.paused;
+                    self.clock.set_paused(self.paused);
+                }
+                TransportPacket::Seek(offset) => {
+                    let target = from_seconds(seconds(self.clock.position()) + offset);
+                    let (start, end) = (self.start, self.start + length);
+                    self.seek(tx, target.max(start).min(end));
+                }
+                TransportPacket::Restart => {
+                    let start = self.start;
+                    self.seek(tx, start);
+                }
+                TransportPacket::Settings(_) => {}
+            }
+        }
+        settings
+    }
+
+    // Whatever is sending live audio keeps on going, so it can't be paused,
+    // but it can be seeked if it's coming from a file
+    fn live_control(&mut self, packet : TransportPacket) {
+        match packet {
+            TransportPacket::TogglePause => println!("Can't pause live audio"),
+            TransportPacket::Seek(offset) => {
+                let from = self.live_seek.unwrap_or(self.clock.position());
+                self.live_seek = Some(from_seconds(seconds(from) + offset));
+            }
+            TransportPacket::Restart => self.live_seek = Some(Duration::new(0, 0)),
+            TransportPacket::Settings(_) => {}
+        }
+    }
+
+    // A seek asked for on live audio since last time
+    pub fn take_live_seek(&mut self) -> Option<Duration> {
+        self.live_seek.take()
+    }
+
+    fn seek(&self, tx : &Sender<AudioPacket>, time : Duration) {
+        self.clock.seek(time);
+        try_send_update(tx, AudioPacket::Reset);
+    }
+}
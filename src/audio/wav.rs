@@ -1,12 +1,8 @@
 use std::u16::MAX as U16MAX;
 use std::io::Read;
-use std::time::Duration;
-use hound::{Sample, WavReader, Error};
-use audio::{Song, AudioData};
-use rodio::{Decoder, Source};
-use rodio;
-use std::io::BufReader;
-use std::fs::File;
+use hound::{Sample, SampleFormat, WavReader, Error};
+use audio::{Song, AudioData, from_seconds};
+use audio::playback::DecodedAudio;
 use std::path::{Path, PathBuf};
 
 pub struct WavSong<T : Read> {
@@ -21,6 +17,12 @@ impl<T : Read> WavSong<T> {
     pub fn new(t : T, path: &Path) -> Result<Self, Error> {
         let reader = WavReader::new(t)?;
         let spec = reader.spec();
+
+        // Hound can only read floats as f32
+        if spec.sample_format == SampleFormat::Float && spec.bits_per_sample != 32 {
+            return Err(Error::Unsupported);
+        }
+
         let name = path.to_path_buf();
         Ok(WavSong {
             reader : reader,
@@ -33,36 +35,65 @@ impl<T : Read> WavSong<T> {
 }
 
 impl<T : Read> Song for WavSong<T> {
-    // This is actually only correct for 16 bit encoded wavs, and will break
-    // for others
-    fn sample_max_value(&self) -> u32 {
-        1 << self.reader.spec().bits_per_sample
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
     }
 
-    fn play(&self) {
-        let endpoint = rodio::get_default_endpoint().unwrap();
-        let file = File::open(self.name.clone()).unwrap();
-        let decoder = rodio::Decoder::new(BufReader::new(file)).unwrap();
+    fn decode(&self) -> Option<DecodedAudio> {
+        // Decode our own copy of the file for the output
+        DecodedAudio::from_file(&self.name, |file| WavSong::new(file, &self.name))
+    }
+}
 
-        rodio::play_raw(&endpoint, decoder.convert_samples());
+impl<T : Read> WavSong<T> {
+    // Read a sample for every channel, normalised to -1..1
+    // Returns None at the end of the file or on a read error
+    fn read_frame(&mut self) -> Option<Vec<f32>> {
+        let spec = self.reader.spec();
+        let channels = self.channels;
+        match spec.sample_format {
+            SampleFormat::Float => {
+                self.reader.samples::<f32>()
+                           .take(channels)
+                           .map(|s| s.ok())
+                           .collect::<Option<Vec<f32>>>()
+            }
+            SampleFormat::Int => {
+                // Hound gives us signed values for every bit depth, including 8
+                let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+                self.reader.samples::<i32>()
+                           .take(channels)
+                           .map(|s| s.ok().map(|x| x as f32 * scale))
+                           .collect::<Option<Vec<f32>>>()
+            }
+        }
     }
 }
 
 impl<T : Read> Iterator for WavSong<T> {
     type Item = AudioData;
     fn next(&mut self) -> Option<Self::Item> {
-        for _ in 0 .. self.channels - 1 {
-            let _ : i32 = self.reader.samples().next().unwrap().unwrap();
+        let frame = match self.read_frame() {
+            Some(x) => x,
+            None => return None,
+        };
+
+        // Stop on a partial frame at the end of the file
+        if frame.len() < self.channels {
+            return None;
         }
-        let x = self.reader.samples().next().map(|s| {
-            let t = self.current_sample as f64 / self.sample_rate as f64;
-            let dur = Duration::from_millis((t * 1000.0) as u64);
-            AudioData {
-                time : dur,
-                sample : s.unwrap(),
-            }
-        });
+
+        // Float files can go over full scale, so clamp to stay in range
+        let samples = frame.iter()
+                           .map(|x| x.max(-1.0).min(1.0))
+                           .collect::<Vec<f32>>();
+
+        let t = self.current_sample as f64 / self.sample_rate as f64;
+        let dur = from_seconds(t);
         self.current_sample += 1;
-        x
+        Some(AudioData {
+            time : dur,
+            samples : samples,
+        })
     }
 }
@@ -0,0 +1,138 @@
+use std::sync::{Mutex, Condvar};
+use std::time::{Duration, Instant};
+use std::thread::sleep;
+
+// The one idea of how far through the song we are that the audio, mapper
+// and graphics all share
+pub trait Clock : Send + Sync {
+    // How far through the song the listener is
+    fn position(&self) -> Duration;
+    fn set_paused(&self, paused : bool);
+    // Jump straight to the given point in the song
+    fn seek(&self, time : Duration);
+
+    // Called by the graphics once per frame drawn
+    fn tick(&self) {
+    }
+
+    // Block until the listener reaches the given point in the song
+    fn wait_until(&self, time : Duration) {
+        if let Some(diff) = time.checked_sub(self.position()) {
+            sleep(diff);
+        }
+    }
+}
+
+// Follows the wall clock, for when nothing is being played that we
+// could follow instead
+pub struct RealTimeClock {
+    state : Mutex<RealTimeState>,
+}
+
+struct RealTimeState {
+    // Where in the song we were at start, Instants can't always go back far
+    // enough to count from the beginning of the song
+    offset : Duration,
+    start : Instant,
+    paused_at : Option<Instant>,
+}
+
+impl RealTimeClock {
+    pub fn new() -> Self {
+        RealTimeClock {
+            state : Mutex::new(RealTimeState {
+                offset : Duration::new(0, 0),
+                start : Instant::now(),
+                paused_at : None,
+            }),
+        }
+    }
+}
+
+impl Clock for RealTimeClock {
+    fn position(&self) -> Duration {
+        let state = self.state.lock().unwrap();
+        let now = state.paused_at.unwrap_or(Instant::now());
+        state.offset + now.duration_since(state.start)
+    }
+
+    fn set_paused(&self, paused : bool) {
+        let mut state = self.state.lock().unwrap();
+        if paused {
+            if state.paused_at.is_none() {
+                state.paused_at = Some(Instant::now());
+            }
+        }
+        else if let Some(paused_at) = state.paused_at.take() {
+            // Shift the start forwards by however long we were paused
+            state.start += paused_at.elapsed();
+        }
+    }
+
+    fn seek(&self, time : Duration) {
+        let mut state = self.state.lock().unwrap();
+        // Count on from the time, starting now
+        let now = state.paused_at.unwrap_or(Instant::now());
+        state.offset = time;
+        state.start = now;
+    }
+}
+
+// Moves forwards a fixed step every frame however long the frame took, so
+// frames can be produced slower or faster than real time, for example
+// when recording the visuals with --step
+pub struct ManualClock {
+    step : Duration,
+    state : Mutex<ManualState>,
+    // Woken whenever the position changes
+    moved : Condvar,
+}
+
+struct ManualState {
+    position : Duration,
+    paused : bool,
+}
+
+impl ManualClock {
+    pub fn new(step : Duration) -> Self {
+        ManualClock {
+            step : step,
+            state : Mutex::new(ManualState {
+                position : Duration::new(0, 0),
+                paused : false,
+            }),
+            moved : Condvar::new(),
+        }
+    }
+}
+
+impl Clock for ManualClock {
+    fn position(&self) -> Duration {
+        self.state.lock().unwrap().position
+    }
+
+    fn set_paused(&self, paused : bool) {
+        self.state.lock().unwrap().paused = paused;
+    }
+
+    fn seek(&self, time : Duration) {
+        self.state.lock().unwrap().position = time;
+        self.moved.notify_all();
+    }
+
+    fn tick(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.paused {
+            state.position += self.step;
+            self.moved.notify_all();
+        }
+    }
+
+    // Rather than sleeping we wait for the ticks to catch up
+    fn wait_until(&self, time : Duration) {
+        let mut state = self.state.lock().unwrap();
+        while state.position < time {
+            state = self.moved.wait(state).unwrap();
+        }
+    }
+}
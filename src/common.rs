@@ -3,15 +3,40 @@ use std::collections::HashMap;
 
 use mapper::Mapper;
 use graphics::ActiveEffects;
+use audio::spectrum::Spectrum;
+use audio::normalise::Normalisation;
+use audio::envelope::Envelope;
+use audio::tags::Tags;
+use audio::midi::MidiFrame;
 
 // audio outputs
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
 pub enum AudioType {
     Impulse,
     Level,
-    //HighFrequency,
-    //MidFrequency,
-    //LowFrequency,
+    LevelLeft,
+    LevelRight,
+    Balance,
+    StereoWidth,
+    Bass,
+    Mid,
+    Treble,
+    Beat,
+    BeatPhase,
+    Bpm,
+    Brightness,
+    Flux,
+    Rolloff,
+    Noisiness,
+    Pitch,
+    Note,
+    Section,
+    SectionProgress,
+    TrackIndex,
+    TrackProgress,
+    Time,
+    Duration,
+    Progress,
     // and many more
 }
 
@@ -35,13 +60,27 @@ pub enum GArg {
 pub enum AudioPacket {
     Update(AudioUpdate),
     Refresh(DeviceStructs),
+    // a new song started, with its tags for anything showing them
+    Song(Tags),
+    // the song jumped, so anything remembered about it is out of date
+    Reset,
 }
 
 pub struct AudioUpdate {
-    pub audio: HashMap<AudioType, f64>,
+    pub audio: AudioFrame,
     pub time: Duration
 }
 
+// everything the analysis produced for one point in the song
+pub struct AudioFrame {
+    pub components: HashMap<AudioType, f64>,
+    pub spectrum: Spectrum,
+    // energy of each pitch class from C to B, the strongest is 1
+    pub chroma: [f64; 12],
+    // notes and controllers from a MIDI file playing along with the song
+    pub midi: MidiFrame,
+}
+
 pub struct DeviceStructs {
     pub bg_mapper: Mapper,
     pub mappers: Vec<Mapper>,
@@ -51,6 +90,26 @@ pub struct DeviceStructs {
 pub enum GraphicsPacket {
     Update(GraphicsUpdate),
     Refresh(ActiveEffects),
+    Song(Tags),
+    Reset,
+}
+
+// packets sent to the audio thread to control playback
+pub enum TransportPacket {
+    TogglePause,
+    // seconds relative to the current position, negative goes backwards
+    Seek(f64),
+    Restart,
+    // the script changed how the audio should be treated
+    Settings(AudioSettings),
+}
+
+// settings from the script for the audio thread
+#[derive(Clone, Debug, Default)]
+pub struct AudioSettings {
+    pub normalisation: Normalisation,
+    // smoothing for the components that have it, the rest follow the audio directly
+    pub envelopes: HashMap<AudioType, Envelope>,
 }
 
 pub struct GraphicsUpdate {
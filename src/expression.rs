@@ -1,5 +1,4 @@
-use common::AudioType;
-use std::collections::HashMap;
+use common::{AudioType, AudioFrame};
 
 // Expressions
 #[derive(Clone,Debug)]
@@ -16,13 +15,17 @@ pub enum Expr {
     Cos(Box<Expr>),
     Floor(Box<Expr>),
     Ceil(Box<Expr>),
+    Spectrum(Box<Expr>, Box<Expr>),
+    Chroma(Box<Expr>),
+    Midi(Box<Expr>, Box<Expr>, Box<Expr>),
+    Cc(Box<Expr>, Box<Expr>),
 }
 
 // Evaluation
 impl Expr {
-    pub fn calculate(self, vars: &HashMap<AudioType,f64>) -> f64 {
+    pub fn calculate(self, vars: &AudioFrame) -> f64 {
         match self {
-            Expr::Var(v) => vars.get(&v).unwrap().clone(),
+            Expr::Var(v) => vars.components.get(&v).unwrap().clone(),
             Expr::Const(x) => x,
             Expr::Add(a,b) => a.calculate(&vars) + b.calculate(&vars),
             Expr::Sub(a,b) => a.calculate(&vars) - b.calculate(&vars),
@@ -38,6 +41,27 @@ impl Expr {
             Expr::Cos(x) => x.calculate(&vars).cos(),
             Expr::Floor(x) => x.calculate(&vars).floor(),
             Expr::Ceil(x) => x.calculate(&vars).ceil(),
+            Expr::Spectrum(lo,hi) => {
+                let lo_hz = lo.calculate(&vars);
+                let hi_hz = hi.calculate(&vars);
+                vars.spectrum.band_level(lo_hz, hi_hz)
+            },
+            Expr::Chroma(n) => {
+                // Wraps around so any whole number is a pitch class
+                let i = n.calculate(&vars).round() as i64;
+                vars.chroma[((i % 12 + 12) % 12) as usize]
+            },
+            Expr::Midi(channel,lo,hi) => {
+                let channel = channel.calculate(&vars).round() as i64;
+                let lo = lo.calculate(&vars).round() as i64;
+                let hi = hi.calculate(&vars).round() as i64;
+                vars.midi.velocity(channel, lo, hi)
+            },
+            Expr::Cc(channel,number) => {
+                let channel = channel.calculate(&vars).round() as i64;
+                let number = number.calculate(&vars).round() as i64;
+                vars.midi.control(channel, number)
+            },
         }
     }
 }
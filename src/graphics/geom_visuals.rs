@@ -1,4 +1,4 @@
-use std::time::{Duration, SystemTime};
+use std::time::Duration;
 use std::collections::HashMap;
 use graphics::opengl_graphics::GlGraphics;
 use graphics::piston::input::RenderArgs;
@@ -51,7 +51,6 @@ fn line_points (gl : &mut GlGraphics,
 }
 
 pub struct CircleVisuals {
-    start_time : SystemTime,
     last_trigger : Duration,
     since_last : u32,
     on : bool,
@@ -64,7 +63,6 @@ impl CircleVisuals {
                              GArg::R,1.0;GArg::G,1.0;GArg::B,1.0;GArg::Trans,1.0;
                              GArg::X,0.5;GArg::Y,0.5];
         CircleVisuals {
-            start_time : SystemTime::now(),
             since_last : 0,
             last_trigger : Duration::new(0, 0),
             on : false,
@@ -123,7 +121,7 @@ impl Visualization for CircleVisuals {
         });
     }
 
-    fn update(&mut self, args: &[(GArg, f64)], args_time: Duration) {
+    fn update(&mut self, args: &[(GArg, f64)], args_time: Duration, now: Duration) {
         self.since_last = self.since_last + 1;
 
         let last_size = arg(&self.vars,GArg::Size);
@@ -140,16 +138,19 @@ impl Visualization for CircleVisuals {
             self.vars.insert(GArg::Size,last_size);
         }
 
-        // Only update if song is playing
-        let _ = self.start_time.elapsed().map(|current_time| {
+        // 50 milliseconds
+        let epilepsy_preventation_duration = Duration::new(0, 50_000_000);
 
-            // 50 milliseconds
-            let epilepsy_preventation_duration = Duration::new(0, 50_000_000);
+        let since_trigger = diff_durs(&now, &self.last_trigger);
 
-            let since_trigger = diff_durs(&current_time, &self.last_trigger);
+        self.on = since_trigger < epilepsy_preventation_duration;
+    }
 
-            self.on = since_trigger < epilepsy_preventation_duration;
-        });
+    fn reset(&mut self) {
+        self.since_last = 0;
+        self.last_trigger = Duration::new(0, 0);
+        self.on = false;
+        self.vars.insert(GArg::Size, 0.0);
     }
 }
 
@@ -218,7 +219,7 @@ impl Visualization for DotsVisuals {
         });
     }
 
-    fn update(&mut self, args: &[(GArg, f64)], args_time: Duration) {
+    fn update(&mut self, args: &[(GArg, f64)], args_time: Duration, now: Duration) {
         self.since_last = self.since_last + 1;
 
         self.size_prev = arg(&self.vars,GArg::Size);
@@ -233,6 +234,13 @@ impl Visualization for DotsVisuals {
             self.angle -= TWO_PI;
         }
     }
+
+    fn reset(&mut self) {
+        self.since_last = 0;
+        self.size_prev = 0.0;
+        self.angle = 0.0;
+        self.angle_prev = 0.0;
+    }
 }
 
 //struct 
@@ -279,7 +287,7 @@ impl Visualization for BarVisuals {
         });
     }
 
-    fn update(&mut self, args: &[(GArg, f64)], args_time: Duration) {
+    fn update(&mut self, args: &[(GArg, f64)], args_time: Duration, now: Duration) {
         self.since_last = self.since_last + 1;
 
         self.size_prev = arg(&self.vars,GArg::Size);
@@ -288,10 +296,14 @@ impl Visualization for BarVisuals {
             self.vars.insert(a,v);
         }
     }
+
+    fn reset(&mut self) {
+        self.since_last = 0;
+        self.size_prev = 0.0;
+    }
 }
 
 pub struct SpikyVisuals {
-    start_time : SystemTime,
     last_trigger : Duration,
     since_last : u32,
     on : bool,
@@ -304,7 +316,6 @@ impl SpikyVisuals {
                              GArg::R,1.0;GArg::G,1.0;GArg::B,1.0;GArg::Trans,1.0;
                              GArg::X,0.5;GArg::Y,0.5];
         SpikyVisuals {
-            start_time : SystemTime::now(),
             since_last : 0,
             last_trigger : Duration::new(0, 0),
             on : false,
@@ -358,7 +369,7 @@ impl Visualization for SpikyVisuals {
         });
     }
 
-    fn update(&mut self, args: &[(GArg, f64)], args_time: Duration) {
+    fn update(&mut self, args: &[(GArg, f64)], args_time: Duration, now: Duration) {
         self.since_last = self.since_last + 1;
 
         let last_size = arg(&self.vars,GArg::Size);
@@ -375,15 +386,18 @@ impl Visualization for SpikyVisuals {
             self.vars.insert(GArg::Size,last_size);
         }
 
-        // Only update if song is playing
-        let _ = self.start_time.elapsed().map(|current_time| {
+        // 50 milliseconds
+        let epilepsy_preventation_duration = Duration::new(0, 50_000_000);
 
-            // 50 milliseconds
-            let epilepsy_preventation_duration = Duration::new(0, 50_000_000);
+        let since_trigger = diff_durs(&now, &self.last_trigger);
 
-            let since_trigger = diff_durs(&current_time, &self.last_trigger);
+        self.on = since_trigger < epilepsy_preventation_duration;
+    }
 
-            self.on = since_trigger < epilepsy_preventation_duration;
-        });
+    fn reset(&mut self) {
+        self.since_last = 0;
+        self.last_trigger = Duration::new(0, 0);
+        self.on = false;
+        self.vars.insert(GArg::Size, 0.0);
     }
 }
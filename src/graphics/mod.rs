@@ -3,26 +3,38 @@ extern crate graphics;
 extern crate glutin_window;
 extern crate opengl_graphics;
 
-use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, Sender};
 
-use common::{GArg, GraphicsPacket, GraphicsUpdate};
+use audio::tags::Tags;
+use common::{GArg, GraphicsPacket, GraphicsUpdate, TransportPacket};
+use clock::Clock;
 use self::glutin_window::GlutinWindow as Window;
 use self::opengl_graphics::{Colored, GlGraphics, OpenGL, Textured};
 use self::piston::event_loop::*;
 use self::piston::window::WindowSettings;
 use self::piston::input::*;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 
 #[macro_use]
 mod common;
 pub mod geom_visuals;
 pub mod backgrounds;
+pub mod text_visuals;
+
+// How far the arrow keys skip through the song
+const SEEK_SECONDS : f64 = 5.0;
 
 
 // trait for visualising a single effect
 pub trait Visualization: Send {
-    fn update(&mut self, args: &[(GArg, f64)], args_time: Duration);
+    // args_time is when the args are for, now is the current song position
+    fn update(&mut self, args: &[(GArg, f64)], args_time: Duration, now: Duration);
     fn render(&self, fps: f64, gl_graphics : &mut GlGraphics, args: &RenderArgs);
+    // forget any history, called when the song jumps
+    fn reset(&mut self);
+    // a new song started, for showing what it's called and so on
+    fn set_tags(&mut self, _tags: &Tags) {}
 }
 
 // trait for backgrounds
@@ -37,13 +49,25 @@ pub struct ActiveEffects {
 }
 
 impl ActiveEffects {
-    fn update_all(&mut self, update: GraphicsUpdate) {
+    fn update_all(&mut self, update: GraphicsUpdate, now: Duration) {
         let (bg_args, effect_args, packet_time) = (update.bg_args, update.effect_args, update.time);
 
         self.bg.update(&bg_args);
     
         for (i, e) in self.effects.iter_mut().enumerate() {
-            e.update(&effect_args[i], packet_time);
+            e.update(&effect_args[i], packet_time, now);
+        }
+    }
+
+    fn reset_all(&mut self) {
+        for e in self.effects.iter_mut() {
+            e.reset();
+        }
+    }
+
+    fn set_tags_all(&mut self, tags: &Tags) {
+        for e in self.effects.iter_mut() {
+            e.set_tags(tags);
         }
     }
 
@@ -71,7 +95,10 @@ impl ActiveEffects {
     }
 }
 
-pub fn run(start_time : SystemTime, rx : Receiver<GraphicsPacket>, effects: ActiveEffects) {
+pub fn run(clock : Arc<Clock>,
+           rx : Receiver<GraphicsPacket>,
+           transport_tx : Sender<TransportPacket>,
+           effects: ActiveEffects) {
     // Try a different version if this doesn't work
     let opengl = OpenGL::V3_3;
 
@@ -87,9 +114,11 @@ pub fn run(start_time : SystemTime, rx : Receiver<GraphicsPacket>, effects: Acti
 
     let mut gl_graphics = GlGraphics::from_colored_textured(c, t);
 
-    let mut prev_time = SystemTime::now();
+    let mut prev_time = Instant::now();
 
     let mut ae = effects;
+    // kept for when the script changes part way through a song
+    let mut tags = Tags::default();
 
     let mut events = Events::new(EventSettings::new());
     while let Some(e) = events.next(&mut window) {
@@ -97,30 +126,59 @@ pub fn run(start_time : SystemTime, rx : Receiver<GraphicsPacket>, effects: Acti
             Input::Render(r) => {
 
                 // Calculate fps
-                let dt = prev_time.elapsed().unwrap();
-                prev_time = SystemTime::now();
+                let dt = prev_time.elapsed();
+                prev_time = Instant::now();
                 let fps = 1000_000_000.0 / (dt.subsec_nanos() as f64); // TODO: is this necessary?
 
                 ae.render_all(fps, &mut gl_graphics, &r, &mut window);
+                clock.tick();
             }
             Input::Update(_) => {
+                let now = clock.position();
+
+                // Go through all the pending packets in order, only the
+                // most recent update matters
+                let mut latest_update = None;
+                for packet in rx.try_iter() {
+                    match packet {
+                        GraphicsPacket::Update(update) => latest_update = Some(update),
+                        GraphicsPacket::Refresh(effects) => {
+                            ae = effects;
+                            ae.set_tags_all(&tags);
+                            latest_update = None;
+                        }
+                        GraphicsPacket::Song(new_tags) => {
+                            ae.set_tags_all(&new_tags);
+                            tags = new_tags;
+                        }
+                        GraphicsPacket::Reset => {
+                            ae.reset_all();
+                            latest_update = None;
+                        }
+                    }
+                }
 
-                // Get all the pending updates from the receiver and buffer into list
-                let mut update_buffer = rx.try_iter().collect::<Vec<GraphicsPacket>>();
-
-                match update_buffer.pop() {
-                    Some(GraphicsPacket::Update(update)) => ae.update_all(update),
-                    Some(GraphicsPacket::Refresh(effects)) => ae = effects,
+                match latest_update {
+                    Some(update) => ae.update_all(update, now),
                     None => {
                         let len = ae.effects.len();
-                        ae.update_all(GraphicsUpdate::new_empty(len))
+                        ae.update_all(GraphicsUpdate::new_empty(len), now)
                     },
                 }
-
-                //ae.update_all(update_buffer);
             }
-            Input::Press(i) => {
-                // Ignore for now
+            Input::Press(Button::Keyboard(key)) => {
+                let packet = match key {
+                    Key::Space => Some(TransportPacket::TogglePause),
+                    Key::Left => Some(TransportPacket::Seek(-SEEK_SECONDS)),
+                    Key::Right => Some(TransportPacket::Seek(SEEK_SECONDS)),
+                    Key::Home => Some(TransportPacket::Restart),
+                    _ => None,
+                };
+
+                // If the audio has finished there's nothing to control
+                if let Some(p) = packet {
+                    let _ = transport_tx.send(p);
+                }
             }
             _ => {}
         }
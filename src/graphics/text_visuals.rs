@@ -0,0 +1,115 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+use std::time::Duration;
+use audio::tags::Tags;
+use common::GArg;
+use graphics::opengl_graphics::{GlGraphics, GlyphCache};
+use graphics::piston::input::RenderArgs;
+use graphics::Visualization;
+use graphics::common::*;
+
+// Set to a .ttf file to choose the font text is drawn in
+const FONT_VARIABLE : &'static str = "AUDISUALS_FONT";
+
+// Otherwise we use the first of these the system has
+const SYSTEM_FONTS : [&'static str; 6] = [
+    "C:\\Windows\\Fonts\\arial.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+];
+
+fn load_font() -> Option<GlyphCache<'static>> {
+    let path = match env::var(FONT_VARIABLE) {
+        Ok(x) => x,
+        Err(_) => match SYSTEM_FONTS.iter().find(|p| Path::new(p).is_file()) {
+            Some(x) => x.to_string(),
+            None => {
+                println!("Couldn't find a font to draw text with, set {} to a .ttf file", FONT_VARIABLE);
+                return None;
+            }
+        }
+    };
+
+    match GlyphCache::new(Path::new(&path)) {
+        Ok(x) => Some(x),
+        Err(e) => {
+            println!("Couldn't load the font {}: {}", path, e);
+            None
+        }
+    }
+}
+
+// Shows one of the playing song's tags, like its title or artist
+pub struct TextVisuals {
+    field : &'static str,
+    text : String,
+    vars : HashMap<GArg, f64>,
+    // Loaded the first time we draw, None if there's no font to draw with
+    font : RefCell<Option<GlyphCache<'static>>>,
+    font_loaded : Cell<bool>,
+}
+
+impl TextVisuals {
+    pub fn new(field : &'static str) -> Self {
+        // Size is the height of the text as a fraction of the window's, and
+        // X and Y are where the start of its baseline goes
+        let vars = make_map![GArg::Size,0.05;
+                             GArg::R,1.0;GArg::G,1.0;GArg::B,1.0;GArg::Trans,1.0;
+                             GArg::X,0.05;GArg::Y,0.95];
+        TextVisuals {
+            field : field,
+            text : String::new(),
+            vars : vars,
+            font : RefCell::new(None),
+            font_loaded : Cell::new(false),
+        }
+    }
+}
+
+impl Visualization for TextVisuals {
+
+    fn render(&self, _fps: f64, gl_graphics : &mut GlGraphics, args: &RenderArgs) {
+        use graphics::graphics::{text, Transformed};
+
+        if self.text.is_empty() {
+            return;
+        }
+
+        if !self.font_loaded.get() {
+            *self.font.borrow_mut() = load_font();
+            self.font_loaded.set(true);
+        }
+
+        let mut font = self.font.borrow_mut();
+        let cache = match *font {
+            Some(ref mut x) => x,
+            None => return,
+        };
+
+        let size = (arg(&self.vars,GArg::Size) * args.height as f64).max(1.0) as u32;
+        let x = arg(&self.vars,GArg::X) * args.width as f64;
+        let y = arg(&self.vars,GArg::Y) * args.height as f64;
+        let color = cons_color(&self.vars);
+
+        gl_graphics.draw(args.viewport(), |c, gl| {
+            let _ = text(color, size, &self.text, cache, c.transform.trans(x, y), gl);
+        });
+    }
+
+    fn update(&mut self, args: &[(GArg, f64)], _args_time: Duration, _now: Duration) {
+        for (a,v) in args.iter().cloned() {
+            self.vars.insert(a,v);
+        }
+    }
+
+    fn reset(&mut self) {}
+
+    fn set_tags(&mut self, tags: &Tags) {
+        self.text = tags.get(self.field).unwrap_or("").to_string();
+    }
+}
@@ -5,31 +5,67 @@ extern crate notify;
 extern crate rodio;
 
 mod audio;
+mod clock;
 mod common;
 mod expression;
 mod graphics;
 mod mapper;
 mod parser;
+mod scripts;
 
 use std::env;
-use std::time::{Duration, SystemTime};
+use std::time::Duration;
 use std::thread;
 use std::thread::sleep;
+use std::sync::Arc;
 use std::sync::mpsc::{Receiver, Sender, channel};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use audio::run_audio;
+use audio::export::ExportFormat;
+use audio::playlist::Playlist;
+use clock::{Clock, RealTimeClock, ManualClock};
 use common::*;
 use mapper::run as run_map;
 use parser::parse_from_file;
+use scripts::ScriptChooser;
 use graphics::run as run_visualizer;
 
 use notify::{Watcher, RecursiveMode, RecommendedWatcher, DebouncedEvent};
+use std::sync::mpsc::RecvTimeoutError;
+
+// The analysis looks a quarter of a second either side of each point
+const SAMPLE_TIME : f64 = 0.25;
+
+// How often the script watcher checks if the song has changed
+const SONG_CHECK_MILLIS : u64 = 20;
 
 fn main() {
 
+    // Write out the analysis instead of showing it
+    if env::args().nth(1).map(|x| x == "--export").unwrap_or(false) {
+        match (env::args().nth(2), env::args().nth(3)) {
+            (Some(x), Some(y)) => export(&x, &y),
+            _ => println!("Usage: audisuals.exe --export music.wav tracks.csv\nOr: cargo run -- --export music.wav tracks.json"),
+        }
+        return;
+    }
+
+    // Draw a fixed number of frames for every second of the song however
+    // long they take, instead of keeping up with the song in real time
+    let step_fps = if env::args().nth(1).map(|x| x == "--step").unwrap_or(false) {
+        match env::args().nth(2).and_then(|x| x.parse::<u32>().ok()) {
+            Some(x) if x > 0 => Some(x),
+            _ => {println!("Usage: audisuals.exe --step 60 music.wav script"); return;},
+        }
+    }
+    else {
+        None
+    };
+    let first_arg = if step_fps.is_some() { 3 } else { 1 };
+
     // Load music file and script
-    let (music_arg, script_arg) = match (env::args().nth(1), env::args().nth(2)) {
+    let (music_arg, script_arg) = match (env::args().nth(first_arg), env::args().nth(first_arg + 1)) {
         (Some(x), Some(y)) => (x, y),
         _ => {println!("Usage: audisuals.exe music.wav script\nOr: cargo run -- music.wav script"); return;},
     };
@@ -37,77 +73,155 @@ fn main() {
     //let mut script_path = env::current_dir().unwrap();
     //script_path.push(&script_arg);
 
-    let (visuals,bg_mapper,mappers) = parse_from_file(&script_arg);
+    // A single song, or a playlist or directory to play one after another
+    let playlist = match Playlist::open(Path::new(&music_arg)) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Error: {}", e);
+            ::std::process::exit(1);
+        }
+    };
+    let song_path = playlist.get(0).to_path_buf();
+
+    // One script for everything, or a map choosing scripts by the songs' tags
+    let scripts = match ScriptChooser::open(Path::new(&script_arg)) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Error: {}", e);
+            ::std::process::exit(1);
+        }
+    };
+    let script_path = scripts.choose(&song_path);
 
-    let song_path = Path::new(&music_arg);
+    let (visuals,bg_mapper,mappers,settings) = parse_from_file(script_path.to_str().unwrap());
 
-    let music_start_time = SystemTime::now();
-    let song = match audio::make_song(&song_path, music_start_time) {
+    let mut song = match audio::make_song(&song_path) {
         Some(x) => x,
         None => {::std::process::exit(1);},
     };
 
-    let countdown = env::args().nth(3).and_then(|x| {
+    let countdown = env::args().nth(first_arg + 2).and_then(|x| {
         x.parse::<u64>().ok()
     }).unwrap_or(0);
 
     // Create a transmitter and receiver for updates
     let (txa, rxa) : (Sender<AudioPacket>, Receiver<AudioPacket>) = channel();
     let (txg, rxg) : (Sender<GraphicsPacket>, Receiver<GraphicsPacket>) = channel();
+    let (txt, rxt) : (Sender<TransportPacket>, Receiver<TransportPacket>) = channel();
+    // the audio says when it moves on to the next song
+    let (txs, rxs) : (Sender<PathBuf>, Receiver<PathBuf>) = channel();
 
     let parser_txa = txa.clone();
-
-    // Program has headstart of a quarter of a second
-    let sample_time = 0.25;
-
-    // TODO: is this still necessary?
-    let music_start_time = SystemTime::now();
+    let parser_txt = txt.clone();
+
+    // Songs are analysed before they start playing so the analysis can see
+    // all of them
+    let analysis = audio::analyse_song(&mut *song, &song_path, SAMPLE_TIME);
+
+    // Everything keeps time with the song as it's played, or with the wall
+    // clock if it can't be. Stepping through frame by frame the song would
+    // drift away from the pictures, so it isn't played.
+    let output = if step_fps.is_some() { None } else { song.play() };
+    let clock : Arc<Clock> = match (step_fps, output.clone()) {
+        (Some(fps), _) => Arc::new(ManualClock::new(Duration::new(0, 1_000_000_000 / fps))),
+        (None, Some(playback)) => Arc::new(playback),
+        (None, None) => Arc::new(RealTimeClock::new()),
+    };
 
     // Start the mapper
+    let map_clock = clock.clone();
     thread::spawn(move || {
-        run_map(rxa, txg, bg_mapper, mappers);
+        run_map(rxa, txg, bg_mapper, mappers, map_clock);
     });
 
     // set up watcher for file refresh
     thread::spawn(move || {
-        watch_script(script_arg.as_str(), parser_txa);
+        watch_script(scripts, script_path, rxs, parser_txa, parser_txt);
     });
 
     // Start the graphics
+    let graphics_clock = clock.clone();
     thread::spawn(move || {
-        run_visualizer(music_start_time, rxg, visuals);
+        run_visualizer(graphics_clock, rxg, txt, visuals);
     });
 
-    // start the audio analysis and playback
-    run_audio(song, txa, sample_time, music_start_time);
+    // start sending the audio components
+    run_audio(song, analysis, playlist, SAMPLE_TIME, settings, txa, rxt, txs, clock, output);
+}
+
+// Analyses a song and writes every audio component to a CSV or JSON file
+fn export(music_arg : &str, out_arg : &str) {
+    let out_path = Path::new(out_arg);
+    let format = match ExportFormat::from_path(out_path) {
+        Some(x) => x,
+        None => {
+            println!("Error: can only export to .csv or .json files");
+            ::std::process::exit(1);
+        }
+    };
+
+    let mut song = match audio::make_song(Path::new(music_arg)) {
+        Some(x) => x,
+        None => {::std::process::exit(1);},
+    };
+
+    match audio::export::export(&mut *song, SAMPLE_TIME, out_path, format) {
+        Ok(n) => println!("Wrote {} updates to {}", n, out_path.display()),
+        Err(e) => {
+            println!("Error: couldn't write {}: {}", out_path.display(), e);
+            ::std::process::exit(1);
+        }
+    }
 }
 
-// watches the script for changes.
-fn watch_script(script_path: &str, txa: Sender<AudioPacket>) {
+// watches the script for changes, and switches to the script for each
+// new song as it starts.
+fn watch_script(scripts: ScriptChooser,
+                script_path: PathBuf,
+                song_rx: Receiver<PathBuf>,
+                txa: Sender<AudioPacket>,
+                txt: Sender<TransportPacket>) {
     let (txf, rxf) = channel();
 
     let mut watcher: RecommendedWatcher = Watcher::new(txf, Duration::from_millis(1)).unwrap();
 
+    let mut script_path = script_path;
     watcher.watch(&script_path, RecursiveMode::NonRecursive).unwrap();
 
     loop {
-        match rxf.recv() {
+        match rxf.recv_timeout(Duration::from_millis(SONG_CHECK_MILLIS)) {
             Ok(event) => match event {
-                DebouncedEvent::Write(_) => {
-                    let (new_visuals, new_bg_mapper, new_mappers) = parse_from_file(&script_path);
-                    let update = AudioPacket::Refresh(DeviceStructs {
-                        bg_mapper: new_bg_mapper,
-                        mappers: new_mappers,
-                        visuals: new_visuals
-                    });
-                    txa.send(update).unwrap();
-                },
+                DebouncedEvent::Write(_) => load_script(&script_path, &txa, &txt),
                 _ => {}
             },
+            Err(RecvTimeoutError::Timeout) => {}
             Err(e) => {
                 println!("Watch error: {:?}", e);
                 break;
             }
         }
+
+        while let Ok(song_path) = song_rx.try_recv() {
+            let new_script = scripts.choose(&song_path);
+            if new_script != script_path {
+                let _ = watcher.unwatch(&script_path);
+                watcher.watch(&new_script, RecursiveMode::NonRecursive).unwrap();
+                script_path = new_script;
+                load_script(&script_path, &txa, &txt);
+            }
+        }
     }
 }
+
+// swaps in a script, replacing everything the last one set up
+fn load_script(script_path: &Path, txa: &Sender<AudioPacket>, txt: &Sender<TransportPacket>) {
+    let (new_visuals, new_bg_mapper, new_mappers, new_settings) = parse_from_file(script_path.to_str().unwrap());
+    // If the audio has finished there's nothing to tell it
+    let _ = txt.send(TransportPacket::Settings(new_settings));
+    let update = AudioPacket::Refresh(DeviceStructs {
+        bg_mapper: new_bg_mapper,
+        mappers: new_mappers,
+        visuals: new_visuals
+    });
+    txa.send(update).unwrap();
+}
@@ -1,7 +1,13 @@
+use std::sync::Arc;
 use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
 
 use common::*;
 use expression::Expr;
+use clock::Clock;
+
+// Updates the listener has gone further past than this aren't worth drawing
+const MAX_LATENESS_MILLIS : u64 = 50;
 
 
 // used to map inputs to a single graphic object
@@ -30,13 +36,21 @@ pub fn run(audio_rx: Receiver<AudioPacket>,
            graphics_tx: Sender<GraphicsPacket>,
            init_bg_mapper: Mapper,
            init_mappers: Vec<Mapper>,
+           clock: Arc<Clock>,
            ) {
+    let max_lateness = Duration::from_millis(MAX_LATENESS_MILLIS);
+
     let mut bg_mapper = init_bg_mapper;
     let mut mappers = init_mappers;
 
     while let Ok(audio_in) = audio_rx.recv(){
         let packet = match audio_in {
             AudioPacket::Update(data) => {
+                // Can happen if we were slow or the song jumped forwards
+                if data.time + max_lateness < clock.position() {
+                    continue;
+                }
+
                 let bg_args = bg_mapper.generate(&data);
                 let effect_args = mappers.iter()
                                          .map(|m| m.generate(&data))
@@ -54,6 +68,8 @@ pub fn run(audio_rx: Receiver<AudioPacket>,
 
                 GraphicsPacket::Refresh(new_structs.visuals)
             }
+            AudioPacket::Song(tags) => GraphicsPacket::Song(tags),
+            AudioPacket::Reset => GraphicsPacket::Reset,
         };
 
         graphics_tx.send(packet).unwrap();
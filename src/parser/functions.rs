@@ -13,7 +13,11 @@ named!(p_func<&[u8], Expr>,
         p_sin_f     |
         p_cos_f     |
         p_floor_f   |
-        p_ceil_f
+        p_ceil_f    |
+        p_spectrum_f |
+        p_chroma_f  |
+        p_midi_f    |
+        p_cc_f
     )
 );
 
@@ -80,3 +84,63 @@ named!(p_ceil_f<&[u8], Expr>,
         (Expr::Ceil(Box::new(v)))
     )
 );
+
+named!(p_spectrum_f<&[u8], Expr>,
+    do_parse!(
+        tag!("spectrum(")   >>
+        opt!(multispace)    >>
+        lo: p_add_sub       >>
+        opt!(multispace)    >>
+        tag!(",")           >>
+        opt!(multispace)    >>
+        hi: p_add_sub       >>
+        opt!(multispace)    >>
+        tag!(")")           >>
+        (Expr::Spectrum(Box::new(lo),Box::new(hi)))
+    )
+);
+
+named!(p_chroma_f<&[u8], Expr>,
+    do_parse!(
+        tag!("chroma(")     >>
+        opt!(multispace)    >>
+        n: p_add_sub        >>
+        opt!(multispace)    >>
+        tag!(")")           >>
+        (Expr::Chroma(Box::new(n)))
+    )
+);
+
+named!(p_midi_f<&[u8], Expr>,
+    do_parse!(
+        tag!("midi(")       >>
+        opt!(multispace)    >>
+        c: p_add_sub        >>
+        opt!(multispace)    >>
+        tag!(",")           >>
+        opt!(multispace)    >>
+        lo: p_add_sub       >>
+        opt!(multispace)    >>
+        tag!(",")           >>
+        opt!(multispace)    >>
+        hi: p_add_sub       >>
+        opt!(multispace)    >>
+        tag!(")")           >>
+        (Expr::Midi(Box::new(c),Box::new(lo),Box::new(hi)))
+    )
+);
+
+named!(p_cc_f<&[u8], Expr>,
+    do_parse!(
+        tag!("cc(")         >>
+        opt!(multispace)    >>
+        c: p_add_sub        >>
+        opt!(multispace)    >>
+        tag!(",")           >>
+        opt!(multispace)    >>
+        n: p_add_sub        >>
+        opt!(multispace)    >>
+        tag!(")")           >>
+        (Expr::Cc(Box::new(c),Box::new(n)))
+    )
+);
@@ -1,6 +1,13 @@
 use std::str;
 use common::{AudioType, GArg};
 use expression::Expr;
+use audio::normalise::Normalisation;
+
+// The settings of an audio component's envelope
+pub enum EnvelopeArg {
+    Attack,
+    Release,
+}
 
 pub fn check_garg_name(i: &[u8]) -> Result<GArg, String> {
     let identifier = str::from_utf8(i).unwrap().to_lowercase();
@@ -24,10 +31,59 @@ pub fn check_garg_name(i: &[u8]) -> Result<GArg, String> {
 
 
 pub fn check_audio_name(i: &[u8]) -> Result<Expr, String> {
+    check_audio_type(i).map(Expr::Var)
+}
+
+pub fn check_audio_type(i: &[u8]) -> Result<AudioType, String> {
     let identifier = str::from_utf8(i).unwrap().to_lowercase();
     match identifier.as_str() {
-        "impulse" => Ok(Expr::Var(AudioType::Impulse)),
-        "level" => Ok(Expr::Var(AudioType::Level)),
+        "impulse" => Ok(AudioType::Impulse),
+        "level" => Ok(AudioType::Level),
+        "levelleft" => Ok(AudioType::LevelLeft),
+        "levelright" => Ok(AudioType::LevelRight),
+        "balance" => Ok(AudioType::Balance),
+        "stereowidth" => Ok(AudioType::StereoWidth),
+        "bass" => Ok(AudioType::Bass),
+        "low" => Ok(AudioType::Bass),
+        "mid" => Ok(AudioType::Mid),
+        "treble" => Ok(AudioType::Treble),
+        "high" => Ok(AudioType::Treble),
+        "beat" => Ok(AudioType::Beat),
+        "beatphase" => Ok(AudioType::BeatPhase),
+        "bpm" => Ok(AudioType::Bpm),
+        "brightness" => Ok(AudioType::Brightness),
+        "flux" => Ok(AudioType::Flux),
+        "rolloff" => Ok(AudioType::Rolloff),
+        "noisiness" => Ok(AudioType::Noisiness),
+        "pitch" => Ok(AudioType::Pitch),
+        "note" => Ok(AudioType::Note),
+        "section" => Ok(AudioType::Section),
+        "sectionprogress" => Ok(AudioType::SectionProgress),
+        "trackindex" => Ok(AudioType::TrackIndex),
+        "trackprogress" => Ok(AudioType::TrackProgress),
+        "time" => Ok(AudioType::Time),
+        "duration" => Ok(AudioType::Duration),
+        "progress" => Ok(AudioType::Progress),
         x => Err(format!("Invalid audio input specified: {}", x)),
     }
 }
+
+pub fn check_normalisation_name(i: &[u8]) -> Result<Normalisation, String> {
+    let identifier = str::from_utf8(i).unwrap().to_lowercase();
+    match identifier.as_str() {
+        "off" => Ok(Normalisation::Off),
+        "none" => Ok(Normalisation::Off),
+        "peak" => Ok(Normalisation::Peak),
+        "loudness" => Ok(Normalisation::Loudness),
+        x => Err(format!("Invalid normalisation specified: {}", x)),
+    }
+}
+
+pub fn check_envelope_arg_name(i: &[u8]) -> Result<EnvelopeArg, String> {
+    let identifier = str::from_utf8(i).unwrap().to_lowercase();
+    match identifier.as_str() {
+        "attack" => Ok(EnvelopeArg::Attack),
+        "release" => Ok(EnvelopeArg::Release),
+        x => Err(format!("Invalid envelope argument specified: {}", x)),
+    }
+}
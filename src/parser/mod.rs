@@ -5,8 +5,11 @@ mod functions;
 use common::*;
 use expression::Expr;
 use mapper::Mapper;
+use audio::normalise::Normalisation;
+use audio::envelope::Envelope;
 use graphics::{Visualization, Background, ActiveEffects};
-use self::keywords::{check_garg_name, check_audio_name};
+use self::keywords::{check_garg_name, check_audio_name, check_audio_type, check_normalisation_name};
+use self::keywords::{check_envelope_arg_name, EnvelopeArg};
 use self::visualizers::{new_visualizer, new_background};
 use self::functions::check_func;
 use nom::IResult;
@@ -17,7 +20,7 @@ use std::fs::File;
 use std::io::Read;
 
 
-pub fn parse_from_file(file_name: &str) -> (ActiveEffects, Mapper, Vec<Mapper>) {
+pub fn parse_from_file(file_name: &str) -> (ActiveEffects, Mapper, Vec<Mapper>, AudioSettings) {
     let mut input_file = File::open(file_name).unwrap();
     let mut file_contents = String::new();
     let _ = input_file.read_to_string(&mut file_contents);
@@ -25,9 +28,9 @@ pub fn parse_from_file(file_name: &str) -> (ActiveEffects, Mapper, Vec<Mapper>)
     parse_from_string(file_contents.as_str())
 }
 
-fn parse_from_string(text: &str) -> (ActiveEffects, Mapper, Vec<Mapper>) {
-    let (bg, mut vis) = match parse_script(text.as_bytes()) {
-        IResult::Done(_,(bg_o, v_o)) => (bg_o, v_o),
+fn parse_from_string(text: &str) -> (ActiveEffects, Mapper, Vec<Mapper>, AudioSettings) {
+    let (settings, bg, mut vis) = match parse_script(text.as_bytes()) {
+        IResult::Done(_,(s_o, bg_o, v_o)) => (s_o, bg_o, v_o),
         IResult::Incomplete(i) => panic!("Incomplete: {:?}", i),
         IResult::Error(e) => panic!("Error: {:?}", e)
     };
@@ -47,17 +50,104 @@ fn parse_from_string(text: &str) -> (ActiveEffects, Mapper, Vec<Mapper>) {
 
     let effects = ActiveEffects {bg: bg_vis, effects: boxes};
 
-    (effects, bg_map, maps)
+    (effects, bg_map, maps, settings)
+}
+
+// A line in the header of the script
+enum Setting {
+    Normalise(Normalisation),
+    Envelopes(Vec<(AudioType, Envelope)>),
 }
 
 // Parser macros
 
-named!(parse_script<&[u8], ((Box<Background>,Mapper), Vec<(Box<Visualization>,Mapper)>)>,
+named!(parse_script<&[u8], (AudioSettings, (Box<Background>,Mapper), Vec<(Box<Visualization>,Mapper)>)>,
     do_parse!(
+        opt!(multispace)    >>
+        s: p_settings       >>
         bg: p_background    >>
         opt!(multispace)    >>
         vm: p_visuals       >>
-        ((bg, vm))
+        ((s, bg, vm))
+    )
+);
+
+named!(p_settings<&[u8], AudioSettings>,
+    do_parse!(
+        list: many0!(do_parse!(
+            s: p_setting        >>
+            opt!(multispace)    >>
+            (s)))           >>
+        (output_settings(list))
+    )
+);
+
+named!(p_setting<&[u8], Setting>,
+    alt!(
+        p_normalise_setting |
+        p_audio_setting
+    )
+);
+
+named!(p_normalise_setting<&[u8], Setting>,
+    do_parse!(
+        alt!(
+            tag!("normalise")  |
+            tag!("normalize")
+        )                   >>
+        opt!(multispace)    >>
+        tag!(":")           >>
+        opt!(multispace)    >>
+        n: p_normalisation  >>
+        (Setting::Normalise(n))
+    )
+);
+
+named!(p_normalisation<&[u8], Normalisation>,
+    map_res!(alpha, check_normalisation_name)
+);
+
+named!(p_audio_setting<&[u8], Setting>,
+    do_parse!(
+        tag!("audio")       >>
+        opt!(multispace)    >>
+        tag!(":")           >>
+        opt!(multispace)    >>
+        list: separated_nonempty_list!(p_comma, p_envelope) >>
+        (Setting::Envelopes(list))
+    )
+);
+
+named!(p_envelope<&[u8], (AudioType, Envelope)>,
+    do_parse!(
+        t: map_res!(alpha, check_audio_type) >>
+        opt!(multispace)    >>
+        tag!("{")           >>
+        opt!(multispace)    >>
+        args: separated_list!(p_comma, p_envelope_arg) >>
+        opt!(multispace)    >>
+        tag!("}")           >>
+        ((t, output_envelope(args)))
+    )
+);
+
+named!(p_envelope_arg<&[u8], (EnvelopeArg, f64)>,
+    do_parse!(
+        a: map_res!(alpha, check_envelope_arg_name) >>
+        opt!(multispace)    >>
+        tag!("=")           >>
+        opt!(multispace)    >>
+        v: map_res!(p_add_sub, check_envelope_time) >>
+        (a,v)
+    )
+);
+
+named!(p_comma<&[u8], ()>,
+    do_parse!(
+        opt!(multispace)    >>
+        tag!(",")           >>
+        opt!(multispace)    >>
+        ()
     )
 );
 
@@ -295,6 +385,38 @@ fn output_background(bg_name: &[u8],
     (bg, map)
 }
 
+fn output_settings(list: Vec<Setting>) -> AudioSettings {
+    // later lines win if a setting is given twice
+    let mut settings = AudioSettings::default();
+    for s in list {
+        match s {
+            Setting::Normalise(n) => settings.normalisation = n,
+            Setting::Envelopes(envs) => settings.envelopes.extend(envs),
+        }
+    }
+    settings
+}
+
+// Envelopes are set up once when the script loads, so can't follow the audio
+fn check_envelope_time(e: Expr) -> Result<f64, String> {
+    match e {
+        Expr::Const(x) if x >= 0.0 => Ok(x),
+        Expr::Const(x) => Err(format!("Envelope times can't be negative: {}", x)),
+        x => Err(format!("Envelope times must be a number: {:?}", x)),
+    }
+}
+
+fn output_envelope(args: Vec<(EnvelopeArg, f64)>) -> Envelope {
+    let mut envelope = Envelope::default();
+    for (arg, seconds) in args {
+        match arg {
+            EnvelopeArg::Attack => envelope.attack = seconds,
+            EnvelopeArg::Release => envelope.release = seconds,
+        }
+    }
+    envelope
+}
+
 fn str_to_int(s: &[u8]) -> Result<i32, String> {
     match str::from_utf8(s) {
         Ok(i_str) => match i_str.parse() {
@@ -1,6 +1,7 @@
 use graphics::*;
 use graphics::backgrounds;
 use graphics::geom_visuals;
+use graphics::text_visuals;
 
 pub fn new_background(name: &str) -> Box<Background> {
     let bg = name.to_lowercase();
@@ -18,6 +19,9 @@ pub fn new_visualizer(name: &str) -> Box<Visualization> {
         "dots" => Box::new(geom_visuals::DotsVisuals::new()),
         "bar" => Box::new(geom_visuals::BarVisuals::new()),
         "spiky" => Box::new(geom_visuals::SpikyVisuals::new()),
+        "title" => Box::new(text_visuals::TextVisuals::new("title")),
+        "artist" => Box::new(text_visuals::TextVisuals::new("artist")),
+        "album" => Box::new(text_visuals::TextVisuals::new("album")),
         x => panic!("Visual function not recognised: {}", x)
     }
 }
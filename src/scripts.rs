@@ -0,0 +1,106 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use audio::tags::Tags;
+
+// A rule from a map file, songs whose field matches the value get the script
+struct Rule {
+    field : String,
+    // Lower case so matching ignores case
+    value : String,
+    script : PathBuf,
+}
+
+// Works out which script to show each song with
+pub struct ScriptChooser {
+    rules : Vec<Rule>,
+    default : PathBuf,
+}
+
+impl ScriptChooser {
+    // Either a script to use for every song, or a .map file of rules picking
+    // scripts by the songs' tags, one on each line:
+    //
+    //     genre: Techno = techno.vis
+    //     artist: Daft Punk = robots.vis
+    //     default = main.vis
+    //
+    // The first rule a song matches wins, and songs that don't match any get
+    // the default. Scripts are relative to the map file.
+    pub fn open(path : &Path) -> Result<Self, String> {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        if ext != "map" {
+            return Ok(ScriptChooser {
+                rules : Vec::new(),
+                default : path.to_path_buf(),
+            });
+        }
+
+        let mut text = String::new();
+        File::open(path).and_then(|mut f| f.read_to_string(&mut text))
+                        .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        let mut rules = Vec::new();
+        let mut default = None;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || format!("Invalid line {} in {}: {}", n + 1, path.display(), line);
+            let mut sides = line.splitn(2, '=');
+            let lhs = sides.next().unwrap_or("").trim();
+            let script = dir.join(sides.next().ok_or_else(&invalid)?.trim());
+            if !script.is_file() {
+                return Err(format!("Couldn't find the script {} in {}", script.display(), path.display()));
+            }
+
+            if lhs.to_lowercase() == "default" {
+                default = Some(script);
+                continue;
+            }
+
+            let mut kv = lhs.splitn(2, ':');
+            let field = kv.next().unwrap_or("").trim().to_lowercase();
+            let value = kv.next().ok_or_else(&invalid)?.trim().to_lowercase();
+            rules.push(Rule {
+                field : field,
+                value : value,
+                script : script,
+            });
+        }
+
+        match default {
+            Some(default) => Ok(ScriptChooser {
+                rules : rules,
+                default : default,
+            }),
+            None => Err(format!("{} needs a default script for songs that don't match", path.display())),
+        }
+    }
+
+    // A script next to the song as [song].vis wins, then the first rule the
+    // song's tags match, then the default
+    pub fn choose(&self, song : &Path) -> PathBuf {
+        let mut sidecar = song.as_os_str().to_owned();
+        sidecar.push(".vis");
+        let sidecar = PathBuf::from(sidecar);
+        if sidecar.is_file() {
+            return sidecar;
+        }
+
+        if !self.rules.is_empty() && song.is_file() {
+            let tags = Tags::read(song);
+            let matched = self.rules.iter().find(|rule| {
+                tags.get(&rule.field).map(|x| x.to_lowercase() == rule.value).unwrap_or(false)
+            });
+            if let Some(rule) = matched {
+                return rule.script.clone();
+            }
+        }
+
+        self.default.clone()
+    }
+}